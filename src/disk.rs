@@ -0,0 +1,1044 @@
+//! On-disk [`PageCache`] implementation
+//!
+//! Pages are stored as files under a local directory, so that the cached
+//! working set survives process restarts. Useful when the working set is
+//! larger than memory but fits on local NVMe.
+//!
+//! ```no_run
+//! # use tokio::runtime::Runtime;
+//! use ocra::disk::DiskCache;
+//!
+//! # let rt = Runtime::new().unwrap();
+//! # rt.block_on(async {
+//! // Use 512 GB of local disk
+//! let cache = DiskCache::builder("/mnt/nvme/ocra", 512 * 1024 * 1024 * 1024)
+//!     .build()
+//!     .await
+//!     .unwrap();
+//! # })
+//! ```
+//!
+//! The directory layout is:
+//!
+//! ```text
+//! <root>/lock                          held by the process using the cache
+//! <root>/page_size                     page size the pages were written with
+//! <root>/<location hash>/location      the object path
+//! <root>/<location hash>/version       e_tag / version and size of the object the pages belong to
+//! <root>/<location hash>/<page id>-<generation>.page
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use futures::FutureExt;
+use log::warn;
+use moka::{future::Cache, notification::RemovalCause, policy::EvictionPolicy};
+use object_store::{path::Path, ObjectMeta};
+
+mod builder;
+mod locations;
+
+pub use self::builder::DiskCacheBuilder;
+use self::locations::{location_hash, LocationEntry, Locations};
+use crate::{
+    paging::{metadata_weight, ObjectVersion, PageCache},
+    stats::CacheStats,
+    Error, Result,
+};
+
+/// Default disk page size is 1 MB
+pub const DEFAULT_PAGE_SIZE: usize = 1024 * 1024;
+const DEFAULT_METADATA_CACHE_SIZE: usize = 32 * 1024 * 1024;
+const METADATA_TIME_TO_IDLE: Duration = Duration::from_secs(60 * 30); // 30 minutes
+
+const LOCK_FILE: &str = "lock";
+const PAGE_SIZE_FILE: &str = "page_size";
+const LOCATION_FILE: &str = "location";
+const VERSION_FILE: &str = "version";
+const PAGE_EXTENSION: &str = "page";
+const TMP_EXTENSION: &str = "tmp";
+
+/// A page stored on disk.
+#[derive(Debug)]
+struct PageFile {
+    path: PathBuf,
+    size: usize,
+}
+
+/// On-disk [`PageCache`] implementation.
+///
+/// The index of pages is kept in memory as a LRU mapping of page IDs to
+/// page files. Evicted pages are removed from disk.
+#[derive(Debug)]
+pub struct DiskCache {
+    /// Root directory of the cache
+    root: PathBuf,
+
+    /// Capacity in bytes
+    capacity: usize,
+
+    /// Size of each page
+    page_size: usize,
+
+    /// Index of the pages on disk: a mapping from `(path id, page id)` to the page file.
+    index: Cache<(u64, u32), Arc<PageFile>>,
+
+    /// Metadata cache
+    metadata_cache: Cache<u64, ObjectMeta>,
+
+    /// Locations with pages or metadata in the cache.
+    locations: Arc<Locations>,
+
+    /// Next page file generation, to never reuse the name of a page file.
+    next_generation: AtomicU64,
+
+    /// Locked while the cache is open, so that no other cache uses the root.
+    _lock: std::fs::File,
+
+    /// Capacity and usage of the cache
+    stats: Arc<dyn CacheStats>,
+}
+
+impl DiskCache {
+    /// Create a [`Builder`](DiskCacheBuilder) to construct [`DiskCache`].
+    ///
+    /// # Parameters:
+    /// - *root*: the directory to store the pages in.
+    /// - *capacity*: capacity in bytes
+    ///
+    /// ```no_run
+    /// # use tokio::runtime::Runtime;
+    /// use ocra::disk::DiskCache;
+    ///
+    /// # let rt = Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// let cache = DiskCache::builder("/tmp/ocra", 8 * 1024 * 1024 * 1024)
+    ///     .page_size(256 * 1024)
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// # })
+    /// ```
+    #[must_use]
+    pub fn builder(root: impl Into<PathBuf>, capacity_bytes: usize) -> DiskCacheBuilder {
+        DiskCacheBuilder::new(root.into(), capacity_bytes)
+    }
+
+    async fn open(
+        root: PathBuf,
+        capacity: usize,
+        page_size: usize,
+        metadata_capacity: usize,
+        stats: Arc<dyn CacheStats>,
+    ) -> Result<Self> {
+        tokio::fs::create_dir_all(&root).await.map_err(io_error)?;
+        let lock = lock_root(&root).await?;
+        stats.set_max_capacity(capacity as u64);
+        let locations = Arc::new(Locations::new(root.clone()));
+        let index = Cache::builder()
+            .max_capacity(capacity as u64)
+            .eviction_policy(EvictionPolicy::lru())
+            // weight each key using the size of the page
+            .weigher(|_key, page: &Arc<PageFile>| -> u32 { page.size as u32 })
+            .async_eviction_listener({
+                let stats = stats.clone();
+                let locations = locations.clone();
+                move |key: Arc<(u64, u32)>, page: Arc<PageFile>, cause| {
+                    let locations = locations.clone();
+                    stats.sub_usage(page.size as u64);
+                    if matches!(cause, RemovalCause::Size | RemovalCause::Expired) {
+                        stats.inc_total_evictions();
+                    }
+                    async move {
+                        if let Err(e) = tokio::fs::remove_file(&page.path).await {
+                            if e.kind() != std::io::ErrorKind::NotFound {
+                                warn!("failed to remove page file {:?}: {}", page.path, e);
+                            }
+                        }
+                        locations.release(key.0).await;
+                    }
+                    .boxed()
+                }
+            })
+            .support_invalidation_closures()
+            .build();
+        let metadata_cache = Cache::builder()
+            .max_capacity(metadata_capacity as u64)
+            .weigher(|_key, meta: &ObjectMeta| -> u32 { metadata_weight(meta) })
+            .time_to_idle(METADATA_TIME_TO_IDLE)
+            .async_eviction_listener({
+                let locations = locations.clone();
+                let stats = stats.clone();
+                move |key: Arc<u64>, meta: ObjectMeta, _cause| {
+                    let locations = locations.clone();
+                    stats.sub_metadata_usage(metadata_weight(&meta) as u64);
+                    async move { locations.release(*key).await }.boxed()
+                }
+            })
+            .build();
+        let cache = Self {
+            root,
+            capacity,
+            page_size,
+            index,
+            metadata_cache,
+            locations,
+            next_generation: AtomicU64::new(0),
+            _lock: lock,
+            stats,
+        };
+        cache.recover().await.map_err(io_error)?;
+        Ok(cache)
+    }
+
+    /// Load the pages written by a previous process into the index.
+    ///
+    /// Pages are inserted from the least to the most recently written one,
+    /// so that the LRU order approximately survives the restart. Pages whose
+    /// size does not match the object they were read from are discarded.
+    async fn recover(&self) -> std::io::Result<()> {
+        let page_size_file = self.root.join(PAGE_SIZE_FILE);
+        let same_page_size = tokio::fs::read_to_string(&page_size_file)
+            .await
+            .is_ok_and(|s| s.trim().parse::<usize>().ok() == Some(self.page_size));
+
+        let mut locations = self.locations.write().await;
+        let mut pages = vec![];
+        let mut max_generation = 0;
+        let mut dirs = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = dirs.next_entry().await? {
+            // Only touch directories that look like ours.
+            let Some(id) = entry
+                .file_name()
+                .to_str()
+                .filter(|name| name.len() == 16)
+                .and_then(|name| u64::from_str_radix(name, 16).ok())
+            else {
+                continue;
+            };
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let dir = entry.path();
+            let location = tokio::fs::read_to_string(dir.join(LOCATION_FILE))
+                .await
+                .ok()
+                .map(Path::from);
+            let location = match location {
+                Some(location) if same_page_size && location_hash(&location) == id => location,
+                _ => {
+                    tokio::fs::remove_dir_all(&dir).await?;
+                    continue;
+                }
+            };
+            let (version, object_size) = tokio::fs::read_to_string(dir.join(VERSION_FILE))
+                .await
+                .ok()
+                .and_then(|s| decode_version(&s))
+                .unzip();
+            let object_size = object_size.flatten();
+
+            // The latest generation of each page in this location.
+            let mut latest: HashMap<u32, (u64, PathBuf)> = HashMap::new();
+            let mut files = tokio::fs::read_dir(&dir).await?;
+            while let Some(file) = files.next_entry().await? {
+                let path = file.path();
+                let Some((page_id, generation)) = parse_page_file_name(&path) else {
+                    if path.extension().is_some_and(|ext| ext == TMP_EXTENSION) {
+                        tokio::fs::remove_file(&path).await?;
+                    }
+                    continue;
+                };
+                max_generation = std::cmp::max(max_generation, generation);
+                match latest.get(&page_id) {
+                    Some((latest_generation, _)) if *latest_generation > generation => {
+                        tokio::fs::remove_file(&path).await?;
+                    }
+                    _ => {
+                        if let Some((_, stale)) = latest.insert(page_id, (generation, path)) {
+                            tokio::fs::remove_file(&stale).await?;
+                        }
+                    }
+                }
+            }
+
+            let mut entries = 0;
+            for (page_id, (_, path)) in latest {
+                let metadata = tokio::fs::metadata(&path).await?;
+                let len = metadata.len() as usize;
+                if !self.is_valid_page(page_id, len, object_size) {
+                    warn!("discarding page file {path:?} of {len} bytes");
+                    tokio::fs::remove_file(&path).await?;
+                    continue;
+                }
+                entries += 1;
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                let page = PageFile {
+                    path,
+                    size: metadata.len() as usize,
+                };
+                pages.push((modified, (id, page_id), page));
+            }
+            if entries == 0 {
+                tokio::fs::remove_dir_all(&dir).await?;
+                continue;
+            }
+            locations.insert(
+                id,
+                LocationEntry {
+                    location,
+                    version,
+                    entries,
+                },
+            );
+        }
+        drop(locations);
+
+        if !same_page_size {
+            tokio::fs::write(&page_size_file, self.page_size.to_string()).await?;
+        }
+
+        self.next_generation
+            .store(max_generation + 1, Ordering::SeqCst);
+        pages.sort_by_key(|(modified, _, _)| *modified);
+        for (_, key, page) in pages {
+            self.stats.inc_usage(page.size as u64);
+            self.index.insert(key, Arc::new(page)).await;
+        }
+        Ok(())
+    }
+
+    /// Whether a page file of `len` bytes can be page `page_id` of an object
+    /// of `object_size` bytes, if known.
+    fn is_valid_page(&self, page_id: u32, len: usize, object_size: Option<usize>) -> bool {
+        match object_size {
+            Some(size) => {
+                let offset = page_id as usize * self.page_size;
+                len == std::cmp::min(size.saturating_sub(offset), self.page_size)
+            }
+            None => len <= self.page_size,
+        }
+    }
+
+    /// Bind the pages of `location_id` to the object version of `meta`.
+    ///
+    /// If the object changed since its pages were cached, the pages are dropped.
+    async fn bind_version(
+        &self,
+        location_id: u64,
+        location: &Path,
+        meta: &ObjectMeta,
+    ) -> std::io::Result<()> {
+        let Some(version) = ObjectVersion::of(meta) else {
+            return Ok(());
+        };
+        if self.locations.has_version(location_id, &version).await {
+            return Ok(());
+        }
+
+        let mut locations = self.locations.write().await;
+        let Some(entry) = locations.get_mut(&location_id) else {
+            return Ok(());
+        };
+        match &entry.version {
+            Some(v) if *v == version => return Ok(()),
+            Some(_) => {
+                // Page files are removed by the eviction listener.
+                self.index
+                    .invalidate_entries_if(move |key, _| key.0 == location_id)
+                    .map_err(std::io::Error::other)?;
+            }
+            None => {}
+        }
+        entry.version = Some(version);
+
+        // Persist the version while holding the lock, so that concurrent
+        // calls write it in order.
+        let dir = self.location_dir(location_id, location).await?;
+        let generation = self.next_generation.fetch_add(1, Ordering::SeqCst);
+        let encoded = encode_version(entry.version.as_ref().unwrap(), meta.size);
+        write_atomic(&dir.join(VERSION_FILE), generation, encoded.as_bytes()).await
+    }
+
+    /// The directory of the location, created if it does not exist.
+    async fn location_dir(&self, id: u64, location: &Path) -> std::io::Result<PathBuf> {
+        let dir = self.locations.dir(id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let location_file = dir.join(LOCATION_FILE);
+        if !tokio::fs::try_exists(&location_file).await? {
+            let generation = self.next_generation.fetch_add(1, Ordering::SeqCst);
+            write_atomic(&location_file, generation, location.as_ref().as_bytes()).await?;
+            // The directory is new.
+            sync_dir(&self.root).await?;
+        }
+        Ok(dir)
+    }
+
+    async fn write_page(
+        &self,
+        id: u64,
+        location: &Path,
+        page_id: u32,
+        data: &Bytes,
+    ) -> std::io::Result<Arc<PageFile>> {
+        // Before writing, so that the directory is not removed meanwhile.
+        if !self.locations.acquire(id, location).await {
+            return Err(std::io::Error::other(format!(
+                "path id of {location} taken by another location"
+            )));
+        }
+        let generation = self.next_generation.fetch_add(1, Ordering::SeqCst);
+        let written = async {
+            let dir = self.location_dir(id, location).await?;
+            let path = dir.join(format!("{page_id}-{generation}.{PAGE_EXTENSION}"));
+            write_atomic(&path, generation, data).await?;
+            Ok(path)
+        }
+        .await;
+        let path = match written {
+            Ok(path) => path,
+            Err(e) => {
+                self.locations.release(id).await;
+                return Err(e);
+            }
+        };
+        self.stats.inc_usage(data.len() as u64);
+        Ok(Arc::new(PageFile {
+            path,
+            size: data.len(),
+        }))
+    }
+}
+
+fn encode_version(version: &ObjectVersion, size: usize) -> String {
+    let mut encoded = String::new();
+    if let Some(e_tag) = &version.e_tag {
+        encoded.push_str(&format!("e_tag {e_tag}\n"));
+    }
+    if let Some(v) = &version.version {
+        encoded.push_str(&format!("version {v}\n"));
+    }
+    encoded.push_str(&format!("size {size}\n"));
+    encoded
+}
+
+/// Decode the version and the size, if known, of an object.
+fn decode_version(encoded: &str) -> Option<(ObjectVersion, Option<usize>)> {
+    let mut version = ObjectVersion {
+        e_tag: None,
+        version: None,
+    };
+    let mut size = None;
+    for line in encoded.lines() {
+        match line.split_once(' ')? {
+            ("e_tag", e_tag) => version.e_tag = Some(e_tag.to_string()),
+            ("version", v) => version.version = Some(v.to_string()),
+            ("size", s) => size = Some(s.parse().ok()?),
+            _ => return None,
+        }
+    }
+    (version.e_tag.is_some() || version.version.is_some()).then_some((version, size))
+}
+
+/// Parse `<page id>-<generation>.page`.
+fn parse_page_file_name(path: &std::path::Path) -> Option<(u32, u64)> {
+    if path.extension()? != PAGE_EXTENSION {
+        return None;
+    }
+    let (page_id, generation) = path.file_stem()?.to_str()?.split_once('-')?;
+    Some((page_id.parse().ok()?, generation.parse().ok()?))
+}
+
+/// Write to a temporary file first, so a crash never leaves a partial file behind.
+///
+/// Both the file and the rename are synced before returning. `generation`
+/// makes the temporary file unique among concurrent writers.
+async fn write_atomic(path: &PathBuf, generation: u64, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("{generation}.{TMP_EXTENSION}"));
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&tmp)
+        .await?
+        .sync_all()
+        .await?;
+    tokio::fs::rename(&tmp, path).await?;
+    match path.parent() {
+        Some(dir) => sync_dir(dir).await,
+        None => Ok(()),
+    }
+}
+
+/// Sync the entries of `dir`, such as files created or renamed in it.
+async fn sync_dir(dir: &std::path::Path) -> std::io::Result<()> {
+    // Directories can only be opened, and synced, on unix.
+    if cfg!(unix) {
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Lock `root`, failing if another cache, in this process or not, uses it.
+async fn lock_root(root: &std::path::Path) -> Result<std::fs::File> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(root.join(LOCK_FILE))
+        .await
+        .map_err(io_error)?
+        .into_std()
+        .await;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => Err(Error::Generic {
+            store: "DiskCache",
+            source: format!("{root:?} is used by another cache").into(),
+        }),
+        Err(std::fs::TryLockError::Error(e)) => Err(io_error(e)),
+    }
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::Generic {
+        store: "DiskCache",
+        source: Box::new(e),
+    }
+}
+
+#[async_trait::async_trait]
+impl PageCache for DiskCache {
+    /// The size of each page.
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Cache capacity in bytes.
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn size(&self) -> usize {
+        self.index.weighted_size() as usize
+    }
+
+    fn stats(&self) -> Arc<dyn CacheStats> {
+        self.stats.clone()
+    }
+
+    async fn get_with(
+        &self,
+        location: &Path,
+        page_id: u32,
+        loader: impl Future<Output = Result<Bytes>> + Send,
+    ) -> Result<Bytes> {
+        let Some(location_id) = self.locations.get_or_insert(location).await else {
+            return loader.await;
+        };
+        let key = (location_id, page_id);
+        if let Some(data) = self.get(location, page_id).await? {
+            return Ok(data);
+        }
+
+        let mut loaded = None;
+        let result = self
+            .index
+            .try_get_with(key, async {
+                let data = loader.await?;
+                loaded = Some(data.clone());
+                self.write_page(location_id, location, page_id, &data)
+                    .await
+                    .map_err(io_error)
+            })
+            .await;
+        if result.is_err() {
+            self.locations.release_if_unused(location_id).await;
+        }
+        match (result, loaded) {
+            (Ok(_), Some(data)) => Ok(data),
+            // Still serve the data if it could not be written to disk.
+            (Err(e), Some(data)) => {
+                warn!("failed to write page {page_id} of {location}: {e}");
+                Ok(data)
+            }
+            // Loaded by a concurrent reader.
+            (Ok(page), None) => Ok(tokio::fs::read(&page.path).await.map_err(io_error)?.into()),
+            (Err(e), None) => match e.as_ref() {
+                Error::NotFound { .. } => Err(Error::NotFound {
+                    path: location.to_string(),
+                    source: Box::new(e),
+                }),
+                Error::Precondition { .. } => Err(Error::Precondition {
+                    path: location.to_string(),
+                    source: Box::new(e),
+                }),
+                Error::NotModified { .. } => Err(Error::NotModified {
+                    path: location.to_string(),
+                    source: Box::new(e),
+                }),
+                _ => Err(Error::Generic {
+                    store: "DiskCache",
+                    source: Box::new(e),
+                }),
+            },
+        }
+    }
+
+    async fn get_range_with(
+        &self,
+        location: &Path,
+        page_id: u32,
+        range: Range<usize>,
+        loader: impl Future<Output = Result<Bytes>> + Send,
+    ) -> Result<Bytes> {
+        assert!(range.start <= range.end && range.end <= self.page_size());
+        let bytes = self.get_with(location, page_id, loader).await?;
+        Ok(bytes.slice(range))
+    }
+
+    async fn get(&self, location: &Path, page_id: u32) -> Result<Option<Bytes>> {
+        let Some(location_id) = self.locations.id(location).await else {
+            return Ok(None);
+        };
+        let key = (location_id, page_id);
+        let Some(page) = self.index.get(&key).await else {
+            return Ok(None);
+        };
+        match tokio::fs::read(&page.path).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) => {
+                // The page file is gone, treat it as a miss.
+                warn!("failed to read page file {:?}: {}", page.path, e);
+                self.index.invalidate(&key).await;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn contains(&self, location: &Path, page_id: u32) -> bool {
+        self.locations
+            .id(location)
+            .await
+            .is_some_and(|id| self.index.contains_key(&(id, page_id)))
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        page_id: u32,
+        range: Range<usize>,
+    ) -> Result<Option<Bytes>> {
+        Ok(self
+            .get(location, page_id)
+            .await?
+            .map(|bytes| bytes.slice(range)))
+    }
+
+    async fn put(&self, location: &Path, page_id: u32, data: Bytes) -> Result<()> {
+        let Some(location_id) = self.locations.get_or_insert(location).await else {
+            return Ok(());
+        };
+        let page = match self.write_page(location_id, location, page_id, &data).await {
+            Ok(page) => page,
+            Err(e) => {
+                self.locations.release_if_unused(location_id).await;
+                return Err(io_error(e));
+            }
+        };
+        self.index.insert((location_id, page_id), page).await;
+        Ok(())
+    }
+
+    async fn head(
+        &self,
+        location: &Path,
+        loader: impl Future<Output = Result<ObjectMeta>> + Send,
+    ) -> Result<ObjectMeta> {
+        let Some(location_id) = self.locations.get_or_insert(location).await else {
+            return loader.await;
+        };
+        let result = self
+            .metadata_cache
+            .try_get_with(location_id, async {
+                let meta = loader.await?;
+                self.locations.acquire(location_id, location).await;
+                self.stats.inc_metadata_usage(metadata_weight(&meta) as u64);
+                Ok::<_, Error>(meta)
+            })
+            .await;
+        let meta = match result {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.locations.release_if_unused(location_id).await;
+                return match e.as_ref() {
+                    Error::NotFound { path, .. } => Err(Error::NotFound {
+                        path: path.to_string(),
+                        source: e.into(),
+                    }),
+                    _ => Err(Error::Generic {
+                        store: "DiskCache",
+                        source: Box::new(e),
+                    }),
+                };
+            }
+        };
+        self.bind_version(location_id, location, &meta)
+            .await
+            .map_err(io_error)?;
+        Ok(meta)
+    }
+
+    async fn invalidate(&self, location: &Path) -> Result<()> {
+        let Some(location_id) = self.locations.id(location).await else {
+            return Ok(());
+        };
+        self.metadata_cache.invalidate(&location_id).await;
+        // Page files are removed by the eviction listener.
+        self.index
+            .invalidate_entries_if(move |key, _| key.0 == location_id)
+            .map_err(|e| Error::Generic {
+                store: "DiskCache",
+                source: Box::new(e),
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use tempfile::tempdir;
+
+    async fn load(
+        cache: &DiskCache,
+        location: &Path,
+        page_id: u32,
+        miss: &AtomicUsize,
+    ) -> Result<Bytes> {
+        cache
+            .get_with(location, page_id, async {
+                miss.fetch_add(1, Ordering::SeqCst);
+                Ok(Bytes::from(vec![page_id as u8; 512]))
+            })
+            .await
+    }
+
+    fn page_files(root: &std::path::Path) -> usize {
+        std::fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .flat_map(|dir| std::fs::read_dir(dir).unwrap())
+            .filter(|entry| parse_page_file_name(&entry.as_ref().unwrap().path()).is_some())
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_get_with_persists_across_restarts() {
+        let tmp_dir = tempdir().unwrap();
+        let location = Path::from("some/file.lance");
+        let miss = AtomicUsize::new(0);
+
+        {
+            let cache = DiskCache::builder(tmp_dir.path(), 4096)
+                .page_size(512)
+                .build()
+                .await
+                .unwrap();
+            for page_id in [0, 1, 0, 2] {
+                let data = load(&cache, &location, page_id, &miss).await.unwrap();
+                assert_eq!(data, vec![page_id as u8; 512]);
+            }
+            assert_eq!(miss.load(Ordering::SeqCst), 3);
+        }
+        assert_eq!(page_files(tmp_dir.path()), 3);
+
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        cache.index.run_pending_tasks().await;
+        assert_eq!(cache.size(), 3 * 512);
+        for page_id in [0, 1, 2] {
+            let data = load(&cache, &location, page_id, &miss).await.unwrap();
+            assert_eq!(data, vec![page_id as u8; 512]);
+        }
+        assert_eq!(miss.load(Ordering::SeqCst), 3);
+
+        // Changing the page size discards all pages.
+        drop(cache);
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(1024)
+            .build()
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_none());
+        assert_eq!(page_files(tmp_dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn test_eviction_removes_files() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = DiskCache::builder(tmp_dir.path(), 1024)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        let location = Path::from("some/file.lance");
+        let miss = AtomicUsize::new(0);
+
+        for page_id in 0..4 {
+            load(&cache, &location, page_id, &miss).await.unwrap();
+            cache.index.run_pending_tasks().await;
+        }
+        assert_eq!(cache.index.entry_count(), 2);
+        assert_eq!(cache.stats().usage(), 2 * 512);
+        assert_eq!(page_files(tmp_dir.path()), 2);
+        assert!(cache.size() <= cache.capacity());
+
+        cache.invalidate(&location).await.unwrap();
+        cache.index.run_pending_tasks().await;
+        assert!(cache.get(&location, 3).await.unwrap().is_none());
+        assert_eq!(cache.stats().usage(), 0);
+        assert_eq!(page_files(tmp_dir.path()), 0);
+    }
+
+    fn location_dirs(root: &std::path::Path) -> usize {
+        std::fs::read_dir(root)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_locations_are_reclaimed() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = DiskCache::builder(tmp_dir.path(), 1024)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        let miss = AtomicUsize::new(0);
+
+        for i in 0..8 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            load(&cache, &location, 0, &miss).await.unwrap();
+            // Failed loads do not keep the location around.
+            let missing = Path::from(format!("some/missing-{i}.lance"));
+            let result = cache
+                .get_with(&missing, 0, async {
+                    Err(Error::NotFound {
+                        path: missing.to_string(),
+                        source: "not found".into(),
+                    })
+                })
+                .await;
+            assert!(matches!(result, Err(Error::NotFound { .. })));
+            cache.index.run_pending_tasks().await;
+        }
+        assert_eq!(cache.locations.len().await, 2);
+        assert_eq!(location_dirs(tmp_dir.path()), 2);
+
+        // Metadata keeps its location, until invalidated.
+        let location = Path::from("some/file-7.lance");
+        cache
+            .head(&location, async {
+                Ok(ObjectMeta {
+                    location: location.clone(),
+                    last_modified: Default::default(),
+                    size: 512,
+                    e_tag: Some("v1".to_string()),
+                    version: None,
+                })
+            })
+            .await
+            .unwrap();
+        for i in 0..8 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            cache.invalidate(&location).await.unwrap();
+        }
+        cache.index.run_pending_tasks().await;
+        cache.metadata_cache.run_pending_tasks().await;
+        assert_eq!(cache.locations.len().await, 0);
+        assert_eq!(location_dirs(tmp_dir.path()), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_capacity() {
+        let tmp_dir = tempdir().unwrap();
+        let meta = |location: &Path| ObjectMeta {
+            location: location.clone(),
+            last_modified: Default::default(),
+            size: 512,
+            e_tag: Some("v1".to_string()),
+            version: None,
+        };
+        let weight = metadata_weight(&meta(&Path::from("some/file-15.lance"))) as usize;
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .metadata_capacity(4 * weight)
+            .build()
+            .await
+            .unwrap();
+
+        for i in 0..16 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            cache
+                .head(&location, async { Ok(meta(&location)) })
+                .await
+                .unwrap();
+        }
+        cache.metadata_cache.run_pending_tasks().await;
+        // Entries are weighed by their size, not counted.
+        assert!(cache.metadata_cache.entry_count() <= 4);
+        assert!(cache.metadata_cache.weighted_size() <= 4 * weight as u64);
+        assert_eq!(
+            cache.stats().metadata_usage(),
+            cache.metadata_cache.weighted_size()
+        );
+        assert_eq!(
+            cache.locations.len().await,
+            cache.metadata_cache.entry_count() as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recover_discards_invalid_pages() {
+        let tmp_dir = tempdir().unwrap();
+        let location = Path::from("some/file.lance");
+        let miss = AtomicUsize::new(0);
+
+        {
+            let cache = DiskCache::builder(tmp_dir.path(), 4096)
+                .page_size(512)
+                .build()
+                .await
+                .unwrap();
+            cache
+                .head(&location, async {
+                    Ok(ObjectMeta {
+                        location: location.clone(),
+                        last_modified: Default::default(),
+                        size: 1024,
+                        e_tag: Some("v1".to_string()),
+                        version: None,
+                    })
+                })
+                .await
+                .unwrap();
+            for page_id in 0..2 {
+                load(&cache, &location, page_id, &miss).await.unwrap();
+            }
+        }
+
+        // A page is truncated while the cache is closed.
+        let dir = tmp_dir
+            .path()
+            .join(format!("{:016x}", location_hash(&location)));
+        let truncated = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| parse_page_file_name(path).is_some_and(|(page_id, _)| page_id == 1))
+            .unwrap();
+        std::fs::write(&truncated, [1; 100]).unwrap();
+
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+        assert!(cache.get(&location, 1).await.unwrap().is_none());
+        assert_eq!(page_files(tmp_dir.path()), 1);
+    }
+
+    #[tokio::test]
+    async fn test_root_is_locked() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        assert!(matches!(
+            DiskCache::builder(tmp_dir.path(), 4096).build().await,
+            Err(Error::Generic { .. })
+        ));
+
+        drop(cache);
+        DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pages_bound_to_version() {
+        let tmp_dir = tempdir().unwrap();
+        let location = Path::from("some/file.lance");
+        let meta = |e_tag: &str| ObjectMeta {
+            location: location.clone(),
+            last_modified: Default::default(),
+            size: 1024,
+            e_tag: Some(e_tag.to_string()),
+            version: None,
+        };
+        let miss = AtomicUsize::new(0);
+
+        {
+            let cache = DiskCache::builder(tmp_dir.path(), 4096)
+                .page_size(512)
+                .build()
+                .await
+                .unwrap();
+            cache
+                .head(&location, async { Ok(meta("v1")) })
+                .await
+                .unwrap();
+            load(&cache, &location, 0, &miss).await.unwrap();
+        }
+
+        // The version survives restarts.
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        cache
+            .head(&location, async { Ok(meta("v1")) })
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+        drop(cache);
+
+        let cache = DiskCache::builder(tmp_dir.path(), 4096)
+            .page_size(512)
+            .build()
+            .await
+            .unwrap();
+        cache
+            .head(&location, async { Ok(meta("v2")) })
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_none());
+        cache.index.run_pending_tasks().await;
+        assert_eq!(page_files(tmp_dir.path()), 0);
+    }
+}
@@ -0,0 +1,75 @@
+//! Disk Cache Builder
+//!
+
+use std::{path::PathBuf, sync::Arc};
+
+use super::{DiskCache, DEFAULT_METADATA_CACHE_SIZE, DEFAULT_PAGE_SIZE};
+use crate::{
+    stats::{AtomicIntCacheStats, CacheStats},
+    Result,
+};
+
+/// Builder for [`DiskCache`]
+pub struct DiskCacheBuilder {
+    root: PathBuf,
+    capacity: usize,
+    page_size: usize,
+    metadata_capacity: usize,
+
+    stats: Option<Arc<dyn CacheStats>>,
+}
+
+impl DiskCacheBuilder {
+    pub(crate) fn new(root: PathBuf, capacity: usize) -> Self {
+        Self {
+            root,
+            capacity,
+            page_size: DEFAULT_PAGE_SIZE,
+            metadata_capacity: DEFAULT_METADATA_CACHE_SIZE,
+            stats: None,
+        }
+    }
+
+    /// Set the page size.
+    ///
+    /// Pages already on disk that were written with a different page size
+    /// are discarded when the cache is opened.
+    pub fn page_size(&mut self, size: usize) -> &mut Self {
+        self.page_size = size;
+        self
+    }
+
+    /// Set the capacity of the in-memory metadata cache, in bytes.
+    ///
+    /// Default is 32 MB.
+    pub fn metadata_capacity(&mut self, capacity_bytes: usize) -> &mut Self {
+        self.metadata_capacity = capacity_bytes;
+        self
+    }
+
+    /// Record the capacity and usage of the cache into `stats`.
+    ///
+    /// By default, each cache has its own [`AtomicIntCacheStats`].
+    pub fn stats(&mut self, stats: Arc<dyn CacheStats>) -> &mut Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Open the cache directory, creating it if it does not exist.
+    ///
+    /// Pages left by a previous process are loaded back into the cache index.
+    pub async fn build(&self) -> Result<DiskCache> {
+        let stats = self
+            .stats
+            .clone()
+            .unwrap_or_else(|| Arc::new(AtomicIntCacheStats::new()));
+        DiskCache::open(
+            self.root.clone(),
+            self.capacity,
+            self.page_size,
+            self.metadata_capacity,
+            stats,
+        )
+        .await
+    }
+}
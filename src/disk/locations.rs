@@ -0,0 +1,157 @@
+//! Locations of the pages on disk
+//!
+
+use std::{collections::HashMap, path::PathBuf};
+
+use log::warn;
+use object_store::path::Path;
+use tokio::sync::{RwLock, RwLockWriteGuard};
+
+use crate::paging::ObjectVersion;
+
+/// A location and the version of the object its pages were read from.
+#[derive(Debug)]
+pub(super) struct LocationEntry {
+    pub(super) location: Path,
+
+    /// `None` until the version is known from [`PageCache::head`](crate::paging::PageCache::head).
+    pub(super) version: Option<ObjectVersion>,
+
+    /// Number of pages and metadata of this location in the cache.
+    pub(super) entries: usize,
+}
+
+/// Locations known to the cache, keyed by the hash of the path.
+///
+/// The hash is stable across restarts, so it is used as the path id. A
+/// location is removed from the table, and its directory from disk, as soon
+/// as it has no pages or metadata left in the cache.
+#[derive(Debug)]
+pub(super) struct Locations {
+    root: PathBuf,
+    table: RwLock<HashMap<u64, LocationEntry>>,
+}
+
+impl Locations {
+    pub(super) fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            table: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The directory of the pages of `id`.
+    pub(super) fn dir(&self, id: u64) -> PathBuf {
+        self.root.join(format!("{id:016x}"))
+    }
+
+    /// Number of locations in the table.
+    #[cfg(test)]
+    pub(super) async fn len(&self) -> usize {
+        self.table.read().await.len()
+    }
+
+    pub(super) async fn write(&self) -> RwLockWriteGuard<'_, HashMap<u64, LocationEntry>> {
+        self.table.write().await
+    }
+
+    /// The path id of `location`, if it is in the table.
+    pub(super) async fn id(&self, location: &Path) -> Option<u64> {
+        let id = location_hash(location);
+        self.table
+            .read()
+            .await
+            .get(&id)
+            .is_some_and(|entry| entry.location == *location)
+            .then_some(id)
+    }
+
+    /// Whether the entries of `id` belong to `version`.
+    pub(super) async fn has_version(&self, id: u64, version: &ObjectVersion) -> bool {
+        self.table
+            .read()
+            .await
+            .get(&id)
+            .is_some_and(|entry| entry.version.as_ref() == Some(version))
+    }
+
+    /// The path id of `location`, adding it to the table if needed.
+    ///
+    /// Returns `None` if the location can not be cached because its hash
+    /// collides with another location. A new location stays in the table
+    /// until [`Self::release_if_unused`] is called, or its first entry is
+    /// released.
+    pub(super) async fn get_or_insert(&self, location: &Path) -> Option<u64> {
+        let id = location_hash(location);
+        if let Some(entry) = self.table.read().await.get(&id) {
+            return (entry.location == *location).then_some(id);
+        }
+
+        let mut table = self.table.write().await;
+        let entry = table.entry(id).or_insert_with(|| LocationEntry {
+            location: location.clone(),
+            version: None,
+            entries: 0,
+        });
+        (entry.location == *location).then_some(id)
+    }
+
+    /// Record that an entry of `location` is being inserted into the cache.
+    ///
+    /// Returns `false` if another location took its path id since.
+    pub(super) async fn acquire(&self, id: u64, location: &Path) -> bool {
+        let mut table = self.table.write().await;
+        let entry = table.entry(id).or_insert_with(|| LocationEntry {
+            location: location.clone(),
+            version: None,
+            entries: 0,
+        });
+        if entry.location != *location {
+            return false;
+        }
+        entry.entries += 1;
+        true
+    }
+
+    /// Record that an entry of `id` was removed from the cache.
+    pub(super) async fn release(&self, id: u64) {
+        let mut table = self.table.write().await;
+        let Some(entry) = table.get_mut(&id) else {
+            return;
+        };
+        entry.entries = entry.entries.saturating_sub(1);
+        if entry.entries == 0 {
+            table.remove(&id);
+            // Still holding the lock, so that no page is written meanwhile.
+            self.remove_dir(id).await;
+        }
+    }
+
+    /// Remove `id` if it has no entries in the cache, e.g. after a failed load.
+    pub(super) async fn release_if_unused(&self, id: u64) {
+        let mut table = self.table.write().await;
+        if table.get(&id).is_some_and(|entry| entry.entries == 0) {
+            table.remove(&id);
+            self.remove_dir(id).await;
+        }
+    }
+
+    async fn remove_dir(&self, id: u64) {
+        let dir = self.dir(id);
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("failed to remove location directory {dir:?}: {e}");
+            }
+        }
+    }
+}
+
+/// FNV-1a hash of the location, stable across processes.
+pub(super) fn location_hash(location: &Path) -> u64 {
+    location
+        .as_ref()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+}
@@ -6,6 +6,8 @@
 //!
 //! For example, you can use [`ReadThroughCache`] to wrap an existing
 //! `ObjectStore` instance with a [`PageCache`](paging::PageCache).
+//! Pages can be kept in memory ([`memory::InMemoryCache`]) or on local
+//! disk ([`disk::DiskCache`]), or both ([`tiered::TieredCache`]).
 //!
 //! ```no_run
 //! # use std::sync::Arc;
@@ -29,13 +31,19 @@
 //! ```
 
 // pub mod error;
+pub mod disk;
 pub mod memory;
+#[cfg(feature = "openmetrics")]
+pub mod openmetrics;
 pub mod paging;
 mod read_through;
 pub mod stats;
+pub mod tiered;
 
 // We reuse `object_store` Error and Result to make this crate work well
 // with the rest of object_store implementations.
 pub use object_store::{Error, Result};
 
-pub use read_through::ReadThroughCache;
+pub use read_through::{
+    AdmissionFilter, PrefetchHandle, ReadAhead, ReadThroughCache, ScanAdmission,
+};
@@ -14,32 +14,60 @@
 //! ```
 
 use std::{
-    collections::HashMap,
     future::Future,
+    hash::Hash,
     ops::Range,
-    sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
-use moka::future::Cache;
+use futures::FutureExt;
+use log::warn;
+use moka::{future::Cache, notification::RemovalCause};
 use object_store::{path::Path, ObjectMeta};
 use sysinfo::{MemoryRefreshKind, RefreshKind};
-use tokio::sync::RwLock;
 
 mod builder;
+mod fifo;
+mod gds;
+mod locations;
+mod pinned;
+mod policy;
 
 pub use self::builder::InMemoryCacheBuilder;
-use crate::{paging::PageCache, Error, Result};
+pub use self::policy::{EvictionPolicy, ExpiryPolicy, PageCost, PageWeigher};
+use self::{
+    locations::{entry_size, Locations},
+    pinned::{Insertion, Pinned},
+    policy::{load_cost, Evictor, MetadataExpiry, PageExpiry, PageWeights, TimeToLive},
+};
+use crate::{
+    paging::{metadata_weight, ObjectVersion, PageCache},
+    stats::CacheStats,
+    Error, Result,
+};
 
 /// Default memory page size is 16 KB
 pub const DEFAULT_PAGE_SIZE: usize = 16 * 1024;
 const DEFAULT_TIME_TO_IDLE: Duration = Duration::from_secs(60 * 30); // 30 minutes
 const DEFAULT_METADATA_CACHE_SIZE: usize = 32 * 1024 * 1024;
+const DEFAULT_PINNED_CACHE_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_NOT_FOUND_CACHE_SIZE: u64 = 64 * 1024;
+
+/// A cached page, with the weight and time to live resolved for its location.
+#[derive(Debug, Clone)]
+struct Page {
+    data: Bytes,
+    weight: u32,
+    time_to_live: Option<Duration>,
+}
 
 /// In-memory [`PageCache`] implementation.
 ///
-/// This is a LRU mapping of page IDs to page data, with TTL eviction.
+/// This is a mapping of page IDs to page data, evicted according to an
+/// [`EvictionPolicy`], with TTL eviction. Pinned pages are kept apart, and
+/// never evicted.
 ///
 #[derive(Debug)]
 pub struct InMemoryCache {
@@ -50,16 +78,33 @@ pub struct InMemoryCache {
     page_size: usize,
 
     /// In memory page cache: a mapping from `(path id, offset)` to data / bytes.
-    cache: Cache<(u64, u32), Bytes>,
+    cache: Cache<(u64, u32), Page>,
 
-    /// Metadata cache
+    /// Metadata cache, weighted by the memory used by each [`ObjectMeta`].
     metadata_cache: Cache<u64, ObjectMeta>,
 
-    /// Provide fast lookup of path id
-    location_lookup: RwLock<HashMap<Path, u64>>,
+    /// Picks the pages to evict, unless moka does.
+    evictor: Option<Arc<Evictor<(u64, u32)>>>,
+
+    /// Picks the metadata to evict, unless moka does.
+    metadata_evictor: Option<Arc<Evictor<u64>>>,
+
+    weights: PageWeights,
+
+    /// Pinned pages, out of the page cache.
+    pinned: Pinned,
+
+    /// Path ids of the locations with pages or metadata in the cache.
+    locations: Arc<Locations>,
+
+    /// Time to live of the pages of each location.
+    time_to_live: TimeToLive,
 
-    /// Next location id to be assigned
-    next_location_id: AtomicU64,
+    /// Locations known not to exist
+    not_found: Option<Cache<Path, ()>>,
+
+    /// Capacity and usage of the page cache
+    stats: Arc<dyn CacheStats>,
 }
 
 impl InMemoryCache {
@@ -89,7 +134,7 @@ impl InMemoryCache {
     /// - `page_size`: The maximum size of each page.
     ///
     pub fn new(capacity_bytes: usize, page_size: usize) -> Self {
-        Self::with_params(capacity_bytes, page_size, DEFAULT_TIME_TO_IDLE)
+        Self::builder(capacity_bytes).page_size(page_size).build()
     }
 
     /// Create a new cache with a size that is a fraction of the system memory
@@ -106,43 +151,271 @@ impl InMemoryCache {
         Self::builder(capacity)
     }
 
-    fn with_params(capacity: usize, page_size: usize, time_to_idle: Duration) -> Self {
-        let cache = Cache::builder()
-            .max_capacity(capacity as u64)
+    fn with_params(builder: &InMemoryCacheBuilder, stats: Arc<dyn CacheStats>) -> Self {
+        let capacity = builder.capacity;
+        let page_size = builder.page_size;
+        let time_to_idle = builder.time_to_idle;
+        let eviction_listener = builder.eviction_listener.clone();
+        stats.set_max_capacity(capacity as u64);
+
+        let locations = Arc::new(Locations::default());
+        let evictor = builder.eviction_policy.evictor(capacity as u64);
+        let metadata_evictor = builder
+            .metadata_eviction_policy
+            .evictor(builder.metadata_capacity as u64);
+
+        let mut cache = Cache::builder()
             // weight each key using the size of the value
-            .weigher(|_key, value: &Bytes| -> u32 { value.len() as u32 })
-            .time_to_idle(time_to_idle)
-            // .eviction_listener(eviction_listener)
-            .build();
-        let metadata_cache = Cache::builder()
-            .max_capacity(DEFAULT_METADATA_CACHE_SIZE as u64)
+            .weigher(|_key, page: &Page| -> u32 { page.weight })
             .time_to_idle(time_to_idle)
-            .build();
+            .expire_after(PageExpiry)
+            .support_invalidation_closures()
+            .async_eviction_listener({
+                let locations = locations.clone();
+                let stats = stats.clone();
+                let evictor = evictor.clone();
+                move |key: Arc<(u64, u32)>, page: Page, cause| {
+                    let listener = eviction_listener.clone();
+                    let locations = locations.clone();
+                    stats.sub_usage(page.data.len() as u64);
+                    let picked = on_remove(evictor.as_deref(), key.as_ref(), cause);
+                    let evicted = is_eviction(cause, picked);
+                    if evicted {
+                        stats.inc_total_evictions();
+                    }
+                    async move {
+                        if let Some(listener) = listener {
+                            if evicted {
+                                // Invalidated or rebound path ids are not in the lookup anymore.
+                                if let Some(location) = locations.location(key.0).await {
+                                    listener(&location, key.1, page.data);
+                                }
+                            }
+                        }
+                        locations.release(key.0).await;
+                    }
+                    .boxed()
+                }
+            });
+        if let Some(policy) = builder.eviction_policy.to_moka() {
+            cache = cache.max_capacity(capacity as u64).eviction_policy(policy);
+        }
+        let cache = cache.build();
+
+        let mut metadata_cache = Cache::builder()
+            .weigher(|_key, meta: &ObjectMeta| -> u32 { metadata_cache_weight(meta) })
+            .time_to_idle(builder.metadata_time_to_idle)
+            .expire_after(MetadataExpiry(TimeToLive {
+                policy: builder.expiry_policy.clone(),
+                default: builder.metadata_time_to_live,
+            }))
+            .async_eviction_listener({
+                let locations = locations.clone();
+                let stats = stats.clone();
+                let evictor = metadata_evictor.clone();
+                move |key: Arc<u64>, meta: ObjectMeta, cause| {
+                    let locations = locations.clone();
+                    stats.sub_metadata_usage(metadata_cache_weight(&meta) as u64);
+                    on_remove(evictor.as_deref(), key.as_ref(), cause);
+                    async move { locations.release(*key).await }.boxed()
+                }
+            });
+        if let Some(policy) = builder.metadata_eviction_policy.to_moka() {
+            metadata_cache = metadata_cache
+                .max_capacity(builder.metadata_capacity as u64)
+                .eviction_policy(policy);
+        }
+        let metadata_cache = metadata_cache.build();
+        let not_found = builder.not_found_ttl.map(|ttl| {
+            Cache::builder()
+                .max_capacity(DEFAULT_NOT_FOUND_CACHE_SIZE)
+                .time_to_live(ttl)
+                .build()
+        });
         Self {
             capacity,
             page_size,
             cache,
             metadata_cache,
-            location_lookup: RwLock::new(HashMap::new()),
-            next_location_id: AtomicU64::new(0),
+            evictor,
+            metadata_evictor,
+            weights: PageWeights {
+                weigher: builder.weigher.clone(),
+                cost: builder.cost.clone(),
+            },
+            pinned: Pinned::new(builder.pinned_capacity as u64, stats.clone()),
+            locations,
+            time_to_live: TimeToLive {
+                policy: builder.expiry_policy.clone(),
+                default: builder.time_to_live,
+            },
+            not_found,
+            stats,
+        }
+    }
+
+    /// Wrap `data` into a page of `location`.
+    fn page(&self, location: &Path, data: Bytes) -> Page {
+        Page {
+            weight: self.weights.weight(location, &data),
+            time_to_live: self.time_to_live.of(location),
+            data,
+        }
+    }
+
+    /// Evict the pages picked by the evictor once `page` was inserted at
+    /// `key`, after loading for `load_time`.
+    async fn admit(
+        &self,
+        location: &Path,
+        key: (u64, u32),
+        page: &Page,
+        load_time: Option<Duration>,
+    ) {
+        let Some(evictor) = &self.evictor else {
+            return;
+        };
+        let cost = self.weights.cost(location, &page.data, load_time);
+        for victim in evictor.on_insert(key, page.weight as u64, cost) {
+            if self.cache.remove(&victim).await.is_none() {
+                evictor.on_remove(&victim);
+            }
+        }
+    }
+
+    /// Insert `data` into the page cache at `key`.
+    async fn insert(&self, location: &Path, key: (u64, u32), data: Bytes) {
+        self.locations.acquire(key.0).await;
+        self.stats.inc_usage(data.len() as u64);
+        let page = self.page(location, data);
+        self.cache.insert(key, page.clone()).await;
+        self.admit(location, key, &page, None).await;
+    }
+
+    /// Keep `data` at `key` out of the page cache, if its page is pinned and
+    /// fits in the pinned capacity. Return whether it was pinned.
+    async fn pin_page(&self, location: &Path, key: (u64, u32), data: &Bytes) -> bool {
+        match self.pinned.insert(location, key, data) {
+            Insertion::Rejected => return false,
+            Insertion::Added => self.locations.acquire(key.0).await,
+            Insertion::Replaced => {}
+        }
+        self.cache.invalidate(&key).await;
+        true
+    }
+
+    /// Evict the metadata picked by the evictor once `meta` was inserted.
+    async fn admit_metadata(
+        &self,
+        location_id: u64,
+        meta: &ObjectMeta,
+        load_time: Option<Duration>,
+    ) {
+        let Some(evictor) = &self.metadata_evictor else {
+            return;
+        };
+        let weight = metadata_cache_weight(meta) as u64;
+        for victim in evictor.on_insert(location_id, weight, load_cost(load_time)) {
+            if self.metadata_cache.remove(&victim).await.is_none() {
+                evictor.on_remove(&victim);
+            }
         }
     }
 
-    async fn location_id(&self, location: &Path) -> u64 {
-        if let Some(&key) = self.location_lookup.read().await.get(location) {
-            return key;
+    /// Fail with [`Error::NotFound`] if `location` is known not to exist.
+    async fn check_not_found(&self, location: &Path) -> Result<()> {
+        match &self.not_found {
+            Some(not_found) if not_found.contains_key(location) => Err(Error::NotFound {
+                path: location.to_string(),
+                source: "not found, cached by InMemoryCache".into(),
+            }),
+            _ => Ok(()),
         }
+    }
 
-        let mut id_map = self.location_lookup.write().await;
-        // on lock-escalation, check if someone else has added it
-        if let Some(&id) = id_map.get(location) {
-            return id;
+    /// Remember that `location` does not exist, if `e` says so.
+    async fn record_not_found(&self, location: &Path, e: &Error) {
+        if let (Some(not_found), Error::NotFound { .. }) = (&self.not_found, e) {
+            not_found.insert(location.clone(), ()).await;
         }
+    }
 
-        let id = self.next_location_id.fetch_add(1, Ordering::SeqCst);
-        id_map.insert(location.clone(), id);
+    async fn forget_not_found(&self, location: &Path) {
+        if let Some(not_found) = &self.not_found {
+            not_found.invalidate(location).await;
+        }
+    }
 
-        id
+    /// Bind the pages of `location_id` to the object version of `meta`.
+    ///
+    /// If the object changed since its pages were cached, the location is moved
+    /// to a new path id, so that the stale pages are never returned again.
+    async fn bind_version(&self, location: &Path, location_id: u64, meta: &ObjectMeta) {
+        let Some(version) = ObjectVersion::of(meta) else {
+            return;
+        };
+        let Some(id) = self
+            .locations
+            .bind_version(location, location_id, version)
+            .await
+        else {
+            return;
+        };
+
+        self.stats
+            .inc_metadata_usage(metadata_cache_weight(meta) as u64);
+        self.metadata_cache.insert(id, meta.clone()).await;
+        self.admit_metadata(id, meta, None).await;
+        if let Err(e) = self.evict(location_id).await {
+            warn!("failed to evict stale pages of {location}: {e}");
+        }
+    }
+
+    /// Remove all pages and the metadata of `location_id` from the cache,
+    /// pinned or not.
+    ///
+    /// Pages are removed by moka in the background, so this does not scan the
+    /// cache on the calling task.
+    async fn evict(&self, location_id: u64) -> Result<()> {
+        self.metadata_cache.invalidate(&location_id).await;
+        for _ in 0..self.pinned.remove(location_id) {
+            self.locations.release(location_id).await;
+        }
+        self.cache
+            .invalidate_entries_if(move |key, _| key.0 == location_id)
+            .map_err(|e| Error::Generic {
+                store: "InMemoryCache",
+                source: Box::new(e),
+            })?;
+        Ok(())
+    }
+}
+
+/// Weight of `meta` in the metadata cache, including the lookup of its path.
+fn metadata_cache_weight(meta: &ObjectMeta) -> u32 {
+    metadata_weight(meta).saturating_add(entry_size(&meta.location) as u32)
+}
+
+/// Report the removal of `key` for `cause` to `evictor`, and return whether
+/// the evictor picked it. Replaced entries are still in the cache.
+fn on_remove<K: Hash + Eq + Clone>(
+    evictor: Option<&Evictor<K>>,
+    key: &K,
+    cause: RemovalCause,
+) -> bool {
+    match evictor {
+        Some(evictor) if cause != RemovalCause::Replaced => evictor.on_remove(key),
+        _ => false,
+    }
+}
+
+/// Whether an entry removed for `cause` was evicted, rather than invalidated
+/// or replaced. Entries `picked` by an [`Evictor`] are removed explicitly.
+fn is_eviction(cause: RemovalCause, picked: bool) -> bool {
+    match cause {
+        RemovalCause::Size | RemovalCause::Expired => true,
+        RemovalCause::Explicit => picked,
+        RemovalCause::Replaced => false,
     }
 }
 
@@ -158,8 +431,16 @@ impl PageCache for InMemoryCache {
         self.capacity
     }
 
+    fn stats(&self) -> Arc<dyn CacheStats> {
+        self.stats.clone()
+    }
+
+    /// Total size of the pages, pinned or not, and object metadata. The
+    /// memory used to look up their paths is part of their weight.
     fn size(&self) -> usize {
         self.cache.weighted_size() as usize
+            + self.pinned.size() as usize
+            + self.metadata_cache.weighted_size() as usize
     }
 
     async fn get_with(
@@ -168,23 +449,72 @@ impl PageCache for InMemoryCache {
         page_id: u32,
         loader: impl Future<Output = Result<Bytes>> + Send,
     ) -> Result<Bytes> {
-        let location_id = self.location_id(location).await;
-        match self
+        self.get_with_latency(location, page_id, async {
+            let started = Instant::now();
+            let data = loader.await?;
+            Ok((data, started.elapsed()))
+        })
+        .await
+    }
+
+    /// The latency is the cost of the page for
+    /// [`EvictionPolicy::GreedyDualSize`], see [`PageCost`].
+    async fn get_with_latency(
+        &self,
+        location: &Path,
+        page_id: u32,
+        loader: impl Future<Output = Result<(Bytes, Duration)>> + Send,
+    ) -> Result<Bytes> {
+        self.check_not_found(location).await?;
+        let location_id = self.locations.get_or_insert(location).await;
+        if let Some(data) = self.pinned.get(&(location_id, page_id)) {
+            return Ok(data);
+        }
+        let mut load_time = None;
+        let result = self
             .cache
-            .try_get_with((location_id, page_id), loader)
-            .await
-        {
-            Ok(bytes) => Ok(bytes),
-            Err(e) => match e.as_ref() {
-                Error::NotFound { .. } => Err(Error::NotFound {
-                    path: location.to_string(),
-                    source: Box::new(e),
-                }),
-                _ => Err(Error::Generic {
-                    store: "InMemoryCache",
-                    source: Box::new(e),
-                }),
-            },
+            .try_get_with((location_id, page_id), async {
+                let (data, latency) = loader.await?;
+                load_time = Some(latency);
+                self.locations.acquire(location_id).await;
+                self.stats.inc_usage(data.len() as u64);
+                Ok::<_, Error>(self.page(location, data))
+            })
+            .await;
+        match result {
+            Ok(page) => {
+                if load_time.is_some() {
+                    let key = (location_id, page_id);
+                    if !self.pin_page(location, key, &page.data).await {
+                        self.admit(location, key, &page, load_time).await;
+                    }
+                } else if let Some(evictor) = &self.evictor {
+                    evictor.on_access(&(location_id, page_id));
+                }
+                Ok(page.data)
+            }
+            Err(e) => {
+                self.locations.release_if_unused(location_id).await;
+                self.record_not_found(location, &e).await;
+                match e.as_ref() {
+                    Error::NotFound { .. } => Err(Error::NotFound {
+                        path: location.to_string(),
+                        source: Box::new(e),
+                    }),
+                    Error::Precondition { .. } => Err(Error::Precondition {
+                        path: location.to_string(),
+                        source: Box::new(e),
+                    }),
+                    Error::NotModified { .. } => Err(Error::NotModified {
+                        path: location.to_string(),
+                        source: Box::new(e),
+                    }),
+                    _ => Err(Error::Generic {
+                        store: "InMemoryCache",
+                        source: Box::new(e),
+                    }),
+                }
+            }
         }
     }
 
@@ -201,8 +531,27 @@ impl PageCache for InMemoryCache {
     }
 
     async fn get(&self, location: &Path, page_id: u32) -> Result<Option<Bytes>> {
-        let location_id = self.location_id(location).await;
-        Ok(self.cache.get(&(location_id, page_id)).await)
+        let Some(location_id) = self.locations.id(location).await else {
+            return Ok(None);
+        };
+        if let Some(data) = self.pinned.get(&(location_id, page_id)) {
+            return Ok(Some(data));
+        }
+        let page = self.cache.get(&(location_id, page_id)).await;
+        if let (Some(evictor), Some(_)) = (&self.evictor, &page) {
+            evictor.on_access(&(location_id, page_id));
+        }
+        Ok(page.map(|page| page.data))
+    }
+
+    async fn contains(&self, location: &Path, page_id: u32) -> bool {
+        self.locations
+            .id(location)
+            .await
+            .is_some_and(|location_id| {
+                let key = (location_id, page_id);
+                self.pinned.contains(&key) || self.cache.contains_key(&key)
+            })
     }
 
     async fn get_range(
@@ -218,8 +567,12 @@ impl PageCache for InMemoryCache {
     }
 
     async fn put(&self, location: &Path, page_id: u32, data: Bytes) -> Result<()> {
-        let location_id = self.location_id(location).await;
-        self.cache.insert((location_id, page_id), data).await;
+        self.forget_not_found(location).await;
+        let location_id = self.locations.get_or_insert(location).await;
+        let key = (location_id, page_id);
+        if !self.pin_page(location, key, &data).await {
+            self.insert(location, key, data).await;
+        }
         Ok(())
     }
 
@@ -228,29 +581,89 @@ impl PageCache for InMemoryCache {
         location: &Path,
         loader: impl Future<Output = Result<ObjectMeta>> + Send,
     ) -> Result<ObjectMeta> {
-        let location_id = self.location_id(location).await;
-        match self.metadata_cache.try_get_with(location_id, loader).await {
-            Ok(meta) => Ok(meta),
-            Err(e) => match e.as_ref() {
-                // TODO: this adds an extra layer of error wrapping
-                Error::NotFound { path, .. } => Err(Error::NotFound {
-                    path: path.to_string(),
-                    source: e.into(),
-                }),
-                _ => Err(Error::Generic {
-                    store: "InMemoryCache",
-                    source: Box::new(e),
-                }),
-            },
+        self.check_not_found(location).await?;
+        let location_id = self.locations.get_or_insert(location).await;
+        let mut load_time = None;
+        let result = self
+            .metadata_cache
+            .try_get_with(location_id, async {
+                let started = Instant::now();
+                let meta = loader.await?;
+                load_time = Some(started.elapsed());
+                self.locations.acquire(location_id).await;
+                self.stats
+                    .inc_metadata_usage(metadata_cache_weight(&meta) as u64);
+                Ok::<_, Error>(meta)
+            })
+            .await;
+        let meta = match result {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.locations.release_if_unused(location_id).await;
+                self.record_not_found(location, &e).await;
+                return match e.as_ref() {
+                    // TODO: this adds an extra layer of error wrapping
+                    Error::NotFound { path, .. } => Err(Error::NotFound {
+                        path: path.to_string(),
+                        source: e.into(),
+                    }),
+                    _ => Err(Error::Generic {
+                        store: "InMemoryCache",
+                        source: Box::new(e),
+                    }),
+                };
+            }
+        };
+        if load_time.is_some() {
+            self.admit_metadata(location_id, &meta, load_time).await;
+        } else if let Some(evictor) = &self.metadata_evictor {
+            evictor.on_access(&location_id);
         }
+        self.bind_version(location, location_id, &meta).await;
+        Ok(meta)
     }
 
     async fn invalidate(&self, location: &Path) -> Result<()> {
-        // Remove the location from lookup table.
-        // This is cheaper (i.e., O(1)) instead of using O(n) to remove all entries from `self.cache`.
-        // The cache will be eventually cleared by the time-to-idle or LRU eviction.
-        let mut id_map = self.location_lookup.write().await;
-        id_map.remove(location);
+        // Remove the location from lookup table first, so that its pages are
+        // unreachable right away, then release them.
+        self.forget_not_found(location).await;
+        let Some(id) = self.locations.remove(location).await else {
+            return Ok(());
+        };
+        self.evict(id).await
+    }
+
+    /// Pinned pages are kept up to the
+    /// [pinned capacity](InMemoryCacheBuilder::pinned_capacity), pages over
+    /// it are cached as any other page.
+    async fn pin(&self, location: &Path, pages: Range<u32>) -> Result<()> {
+        self.pinned.pin(location, pages.clone());
+        let Some(location_id) = self.locations.id(location).await else {
+            return Ok(());
+        };
+        // Move the pages already cached out of the page cache. They are only
+        // looked up within the object, those cached without its metadata are
+        // pinned once loaded again.
+        let Some(meta) = self.metadata_cache.get(&location_id).await else {
+            return Ok(());
+        };
+        let num_pages = meta.size.div_ceil(self.page_size);
+        let end = std::cmp::min(pages.end as usize, num_pages) as u32;
+        for page_id in pages.start..end {
+            let key = (location_id, page_id);
+            if let Some(page) = self.cache.get(&key).await {
+                self.pin_page(location, key, &page.data).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn unpin(&self, location: &Path, pages: Range<u32>) -> Result<()> {
+        let location_id = self.locations.id(location).await;
+        for (key, data) in self.pinned.unpin(location, location_id, pages) {
+            self.insert(location, key, data).await;
+            self.locations.release(key.0).await;
+        }
         Ok(())
     }
 }
@@ -316,7 +729,6 @@ mod tests {
     #[tokio::test]
     async fn test_eviction() {
         const PAGE_SIZE: usize = 512;
-        let cache = InMemoryCache::new(1024, PAGE_SIZE);
         let local_fs = Arc::new(LocalFileSystem::new());
 
         let tmp_dir = tempdir().unwrap();
@@ -328,7 +740,7 @@ mod tests {
             }
         }
         let location = Path::from(file_path.as_path().to_str().unwrap());
-        cache.cache.run_pending_tasks().await;
+        let cache = InMemoryCache::new(2 * page_weight(&location, PAGE_SIZE), PAGE_SIZE);
 
         let miss = Arc::new(AtomicUsize::new(0));
 
@@ -398,4 +810,502 @@ mod tests {
             .unwrap();
         assert_eq!(meta.size, 9);
     }
+
+    /// Weight of a page of `len` bytes of `location`.
+    fn page_weight(location: &Path, len: usize) -> usize {
+        len + entry_size(location)
+    }
+
+    fn meta(location: &Path, e_tag: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: location.clone(),
+            last_modified: Default::default(),
+            size: 512,
+            e_tag: Some(e_tag.to_string()),
+            version: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pages_bound_to_version() {
+        let cache = InMemoryCache::new(4096, 512);
+        let location = Path::from("some/file.lance");
+
+        let m = cache
+            .head(&location, async { Ok(meta(&location, "v1")) })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v1"));
+        cache
+            .put(&location, 0, Bytes::from(vec![1; 512]))
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+
+        // Same version, pages are kept.
+        let location_id = cache.locations.id(&location).await.unwrap();
+        cache.metadata_cache.invalidate(&location_id).await;
+        cache
+            .head(&location, async { Ok(meta(&location, "v1")) })
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+
+        // The object was overwritten, pages of the old version are gone.
+        cache.metadata_cache.invalidate(&location_id).await;
+        let m = cache
+            .head(&location, async { Ok(meta(&location, "v2")) })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v2"));
+        assert!(cache.get(&location, 0).await.unwrap().is_none());
+        let m = cache
+            .head(&location, async { panic!("metadata should be cached") })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v2"));
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_releases_memory() {
+        let cache = InMemoryCache::new(4096, 512);
+        let location = Path::from("some/file.lance");
+        let other = Path::from("some/other.lance");
+
+        cache
+            .head(&location, async { Ok(meta(&location, "v1")) })
+            .await
+            .unwrap();
+        for page_id in 0..4 {
+            cache
+                .put(&location, page_id, Bytes::from(vec![1; 512]))
+                .await
+                .unwrap();
+        }
+        cache
+            .put(&other, 0, Bytes::from(vec![2; 512]))
+            .await
+            .unwrap();
+        cache.cache.run_pending_tasks().await;
+        cache.metadata_cache.run_pending_tasks().await;
+        assert_eq!(
+            cache.cache.weighted_size() as usize,
+            4 * page_weight(&location, 512) + page_weight(&other, 512)
+        );
+        assert_eq!(cache.stats().usage(), 5 * 512);
+        assert_eq!(cache.metadata_cache.entry_count(), 1);
+        assert_eq!(
+            cache.stats().metadata_usage(),
+            metadata_cache_weight(&meta(&location, "v1")) as u64
+        );
+
+        cache.invalidate(&location).await.unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_none());
+        cache.cache.run_pending_tasks().await;
+        cache.metadata_cache.run_pending_tasks().await;
+        assert_eq!(
+            cache.cache.weighted_size() as usize,
+            page_weight(&other, 512)
+        );
+        assert_eq!(cache.stats().usage(), 512);
+        assert_eq!(cache.cache.entry_count(), 1);
+        assert_eq!(cache.metadata_cache.entry_count(), 0);
+        assert_eq!(cache.stats().metadata_usage(), 0);
+        assert!(cache.get(&other, 0).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_eviction_policies() {
+        async fn scan(policy: EvictionPolicy) -> (InMemoryCache, Path, Arc<AtomicUsize>) {
+            let demoted = Arc::new(AtomicUsize::new(0));
+            let index = Path::from("some/file.index");
+            let data = Path::from("some/data.lance");
+            let cache = InMemoryCache::builder(8 * page_weight(&data, 512))
+                .page_size(512)
+                .eviction_policy(policy)
+                .eviction_listener({
+                    let demoted = demoted.clone();
+                    Arc::new(move |_, _, _| {
+                        demoted.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+                .build();
+            for page_id in 0..8 {
+                cache
+                    .put(&index, page_id, Bytes::from(vec![1; 512]))
+                    .await
+                    .unwrap();
+            }
+            for page_id in 0..4 {
+                assert!(cache.get(&index, page_id).await.unwrap().is_some());
+            }
+
+            for page_id in 0..16 {
+                cache
+                    .get_with(&data, page_id, async { Ok(Bytes::from(vec![2; 512])) })
+                    .await
+                    .unwrap();
+            }
+            cache.cache.run_pending_tasks().await;
+            assert_eq!(cache.cache.entry_count(), 8);
+            assert_eq!(
+                cache.cache.weighted_size() as usize,
+                8 * page_weight(&data, 512)
+            );
+            assert_eq!(cache.stats().usage(), 8 * 512);
+            assert_eq!(cache.stats().total_evictions(), 16);
+            (cache, index, demoted)
+        }
+
+        // The pages read again survive the scan.
+        let (cache, index, demoted) = scan(EvictionPolicy::S3Fifo).await;
+        for page_id in 0..4 {
+            assert!(cache.get(&index, page_id).await.unwrap().is_some());
+        }
+        assert!(cache.get(&index, 4).await.unwrap().is_none());
+        assert_eq!(demoted.load(Ordering::SeqCst), 16);
+
+        let (cache, index, demoted) = scan(EvictionPolicy::Fifo).await;
+        for page_id in 0..8 {
+            assert!(cache.get(&index, page_id).await.unwrap().is_none());
+        }
+        assert_eq!(demoted.load(Ordering::SeqCst), 16);
+
+        // Invalidated pages free their space.
+        cache
+            .invalidate(&Path::from("some/data.lance"))
+            .await
+            .unwrap();
+        cache.cache.run_pending_tasks().await;
+        cache
+            .put(&index, 0, Bytes::from(vec![1; 512]))
+            .await
+            .unwrap();
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.cache.entry_count(), 1);
+        assert_eq!(cache.stats().total_evictions(), 16);
+    }
+
+    #[test]
+    fn test_is_eviction() {
+        assert!(is_eviction(RemovalCause::Size, false));
+        assert!(is_eviction(RemovalCause::Expired, false));
+        assert!(is_eviction(RemovalCause::Explicit, true));
+        assert!(!is_eviction(RemovalCause::Explicit, false));
+        assert!(!is_eviction(RemovalCause::Replaced, false));
+
+        // Only the entries removed from the cache are reported to the evictor.
+        let evictor = EvictionPolicy::Fifo.evictor(1).unwrap();
+        assert!(evictor.on_insert(0, 1, 0.0).is_empty());
+        assert!(!on_remove(Some(&evictor), &0, RemovalCause::Replaced));
+        assert_eq!(evictor.on_insert(1, 1, 0.0), vec![0]);
+        assert!(on_remove(Some(&evictor), &0, RemovalCause::Explicit));
+        assert!(!on_remove(None, &1, RemovalCause::Explicit));
+    }
+
+    #[tokio::test]
+    async fn test_cost_aware_eviction() {
+        let remote = Path::from("remote/data.lance");
+        let local = Path::from("local/data.lance");
+        let cache = InMemoryCache::builder(4 * page_weight(&remote, 512))
+            .page_size(512)
+            .eviction_policy(EvictionPolicy::GreedyDualSize)
+            .build();
+
+        // Slow pages are kept over the ones faster to load again.
+        cache
+            .get_with_latency(&remote, 0, async {
+                Ok((Bytes::from(vec![1; 512]), Duration::from_millis(20)))
+            })
+            .await
+            .unwrap();
+        for page_id in 0..8 {
+            cache
+                .get_with_latency(&local, page_id, async {
+                    Ok((Bytes::from(vec![2; 512]), Duration::from_millis(1)))
+                })
+                .await
+                .unwrap();
+        }
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.cache.entry_count(), 4);
+        assert_eq!(cache.stats().total_evictions(), 5);
+        assert!(cache.get(&remote, 0).await.unwrap().is_some());
+
+        // Both weight and cost can be picked per page.
+        let cache = InMemoryCache::builder(4)
+            .page_size(512)
+            .eviction_policy(EvictionPolicy::GreedyDualSize)
+            .weigher(Arc::new(|_, _| 1))
+            .cost(Arc::new(|location, _, _| {
+                if location.prefix_matches(&Path::from("remote")) {
+                    100.0
+                } else {
+                    1.0
+                }
+            }))
+            .build();
+        cache
+            .put(&remote, 0, Bytes::from(vec![1; 512]))
+            .await
+            .unwrap();
+        for page_id in 0..8 {
+            cache
+                .put(&local, page_id, Bytes::from(vec![2; 512]))
+                .await
+                .unwrap();
+        }
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.cache.entry_count(), 4);
+        assert_eq!(cache.cache.weighted_size(), 4);
+        assert!(cache.get(&remote, 0).await.unwrap().is_some());
+        assert!(cache.get(&local, 7).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_time_to_live() {
+        let cache = InMemoryCache::builder(4096)
+            .page_size(512)
+            .time_to_live(Duration::from_millis(50))
+            .build();
+        let location = Path::from("some/file.lance");
+        cache
+            .put(&location, 0, Bytes::from(vec![1; 512]))
+            .await
+            .unwrap();
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.get(&location, 0).await.unwrap().is_none());
+
+        // Only the manifest expires, its data files live as long as they are used.
+        let cache = InMemoryCache::builder(4096)
+            .page_size(512)
+            .time_to_live(Duration::from_millis(50))
+            .expiry_policy(Arc::new(|location| {
+                location
+                    .extension()
+                    .is_some_and(|ext| ext == "manifest")
+                    .then_some(Duration::from_millis(50))
+            }))
+            .build();
+        let manifest = Path::from("some/_latest.manifest");
+        for location in [&location, &manifest] {
+            cache
+                .head(location, async { Ok(meta(location, "v1")) })
+                .await
+                .unwrap();
+            cache
+                .put(location, 0, Bytes::from(vec![1; 512]))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.get(&location, 0).await.unwrap().is_some());
+        assert!(cache.get(&manifest, 0).await.unwrap().is_none());
+        cache
+            .head(&location, async { panic!("metadata should be cached") })
+            .await
+            .unwrap();
+        let m = cache
+            .head(&manifest, async { Ok(meta(&manifest, "v2")) })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v2"));
+    }
+
+    #[tokio::test]
+    async fn test_pinned_pages() {
+        let cache = InMemoryCache::builder(4 * 512)
+            .page_size(512)
+            .pinned_capacity(2 * 512)
+            .time_to_idle(Duration::from_millis(50))
+            .build();
+        let manifest = Path::from("some/_latest.manifest");
+        cache
+            .head(&manifest, async { Ok(meta(&manifest, "v1")) })
+            .await
+            .unwrap();
+        cache
+            .put(&manifest, 0, Bytes::from(vec![1; 512]))
+            .await
+            .unwrap();
+
+        // Cached pages are pinned right away, the others once loaded, up to
+        // the pinned capacity.
+        cache.pin(&manifest, 0..u32::MAX).await.unwrap();
+        for page_id in 1..3 {
+            cache
+                .get_with(&manifest, page_id, async { Ok(Bytes::from(vec![1; 512])) })
+                .await
+                .unwrap();
+        }
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.stats().pinned_usage(), 2 * 512);
+        assert_eq!(cache.stats().usage(), 512);
+        assert_eq!(cache.cache.entry_count(), 1);
+
+        // Pinned pages are neither evicted nor expired.
+        let data = Path::from("some/data.lance");
+        for page_id in 0..16 {
+            cache
+                .get_with(&data, page_id, async { Ok(Bytes::from(vec![2; 512])) })
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cache.cache.run_pending_tasks().await;
+        for page_id in 0..2 {
+            assert!(cache.get(&manifest, page_id).await.unwrap().is_some());
+            assert!(cache.contains(&manifest, page_id).await);
+        }
+        assert!(cache.get(&manifest, 2).await.unwrap().is_none());
+
+        // Unpinned pages are cached as the others.
+        cache.unpin(&manifest, 0..1).await.unwrap();
+        assert_eq!(cache.stats().pinned_usage(), 512);
+        assert!(cache.get(&manifest, 0).await.unwrap().is_some());
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.stats().usage(), 512);
+
+        // Invalidated pages are dropped, and pinned again once loaded.
+        cache.invalidate(&manifest).await.unwrap();
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.stats().pinned_usage(), 0);
+        assert_eq!(cache.stats().usage(), 0);
+        assert_eq!(cache.locations.len().await, 0);
+        cache
+            .put(&manifest, 1, Bytes::from(vec![3; 512]))
+            .await
+            .unwrap();
+        assert_eq!(cache.stats().pinned_usage(), 512);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_capacity() {
+        let weight = metadata_cache_weight(&meta(&Path::from("some/file-15.lance"), "v1")) as usize;
+        let cache = InMemoryCache::builder(4096)
+            .page_size(512)
+            .metadata_capacity(4 * weight)
+            .metadata_eviction_policy(EvictionPolicy::Lru)
+            .build();
+
+        for i in 0..16 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            cache
+                .head(&location, async { Ok(meta(&location, "v1")) })
+                .await
+                .unwrap();
+        }
+        cache.metadata_cache.run_pending_tasks().await;
+        // Entries are weighed by their size, not counted.
+        assert_eq!(cache.metadata_cache.entry_count(), 4);
+        assert_eq!(cache.metadata_cache.weighted_size(), 4 * weight as u64);
+        assert_eq!(cache.stats().metadata_usage(), 4 * weight as u64);
+        assert_eq!(cache.stats().usage(), 0);
+
+        // The most recent entries are kept.
+        let location = Path::from("some/file-15.lance");
+        cache
+            .head(&location, async { panic!("metadata should be cached") })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_location_ids_are_reclaimed() {
+        let cache = InMemoryCache::new(4 * 512, 512);
+
+        for i in 0..64 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            cache
+                .get_with(&location, 0, async { Ok(Bytes::from(vec![1; 512])) })
+                .await
+                .unwrap();
+            // Failed loads do not keep the path around.
+            let missing = Path::from(format!("some/missing-{i}.lance"));
+            let result = cache
+                .get_with(&missing, 0, async {
+                    Err(Error::NotFound {
+                        path: missing.to_string(),
+                        source: "not found".into(),
+                    })
+                })
+                .await;
+            assert!(matches!(result, Err(Error::NotFound { .. })));
+            assert!(cache.get(&missing, 0).await.unwrap().is_none());
+        }
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.cache.entry_count() <= 4);
+        assert_eq!(
+            cache.locations.len().await,
+            cache.cache.entry_count() as usize
+        );
+        assert!(cache.size() <= cache.capacity());
+
+        for i in 0..64 {
+            let location = Path::from(format!("some/file-{i}.lance"));
+            cache.invalidate(&location).await.unwrap();
+        }
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.locations.len().await, 0);
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_cache() {
+        let cache = InMemoryCache::builder(1024)
+            .page_size(16)
+            .not_found_ttl(Duration::from_secs(60))
+            .build();
+        let location = Path::from("does/not/exist");
+        let loads = AtomicUsize::new(0);
+        async fn not_found<T>(loads: &AtomicUsize, location: &Path) -> Result<T> {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Err(Error::NotFound {
+                path: location.to_string(),
+                source: "missing".into(),
+            })
+        }
+
+        for _ in 0..3 {
+            let result = cache.head(&location, not_found(&loads, &location)).await;
+            assert!(matches!(result, Err(Error::NotFound { .. })));
+            let result = cache
+                .get_with(&location, 0, not_found(&loads, &location))
+                .await;
+            assert!(matches!(result, Err(Error::NotFound { .. })));
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // Other errors are not cached.
+        let other = Path::from("other");
+        for _ in 0..2 {
+            let result = cache
+                .head(&other, async {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    Err(Error::NotImplemented)
+                })
+                .await;
+            assert!(result.is_err());
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+
+        cache.invalidate(&location).await.unwrap();
+        assert!(cache
+            .head(&location, not_found(&loads, &location))
+            .await
+            .is_err());
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+
+        cache
+            .put(&location, 0, Bytes::from_static(b"now it exists"))
+            .await
+            .unwrap();
+        let data = cache
+            .get_with(&location, 0, not_found(&loads, &location))
+            .await
+            .unwrap();
+        assert_eq!(data, "now it exists");
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+    }
 }
@@ -1,16 +1,43 @@
 //! Memory Cache Builder
 //!
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use super::{InMemoryCache, DEFAULT_PAGE_SIZE, DEFAULT_TIME_TO_IDLE};
+use super::{
+    EvictionPolicy, ExpiryPolicy, InMemoryCache, PageCost, PageWeigher,
+    DEFAULT_METADATA_CACHE_SIZE, DEFAULT_PAGE_SIZE, DEFAULT_PINNED_CACHE_SIZE,
+    DEFAULT_TIME_TO_IDLE,
+};
+use crate::{
+    paging::EvictionListener,
+    stats::{AtomicIntCacheStats, CacheStats},
+};
 
 /// Builder for [`InMemoryCache`]
 pub struct InMemoryCacheBuilder {
-    capacity: usize,
-    page_size: usize,
+    pub(super) capacity: usize,
+    pub(super) page_size: usize,
 
-    time_to_idle: Duration,
+    pub(super) eviction_policy: EvictionPolicy,
+    pub(super) weigher: Option<PageWeigher>,
+    pub(super) cost: Option<PageCost>,
+
+    pub(super) time_to_idle: Duration,
+    pub(super) time_to_live: Option<Duration>,
+    pub(super) expiry_policy: Option<ExpiryPolicy>,
+
+    pub(super) not_found_ttl: Option<Duration>,
+
+    pub(super) metadata_capacity: usize,
+    pub(super) metadata_time_to_idle: Duration,
+    pub(super) metadata_time_to_live: Option<Duration>,
+    pub(super) metadata_eviction_policy: EvictionPolicy,
+
+    pub(super) pinned_capacity: usize,
+
+    pub(super) eviction_listener: Option<EvictionListener>,
+
+    pub(super) stats: Option<Arc<dyn CacheStats>>,
 }
 
 impl InMemoryCacheBuilder {
@@ -18,7 +45,20 @@ impl InMemoryCacheBuilder {
         Self {
             capacity,
             page_size: DEFAULT_PAGE_SIZE,
+            eviction_policy: EvictionPolicy::default(),
+            weigher: None,
+            cost: None,
             time_to_idle: DEFAULT_TIME_TO_IDLE,
+            time_to_live: None,
+            expiry_policy: None,
+            not_found_ttl: None,
+            metadata_capacity: DEFAULT_METADATA_CACHE_SIZE,
+            metadata_time_to_idle: DEFAULT_TIME_TO_IDLE,
+            metadata_time_to_live: None,
+            metadata_eviction_policy: EvictionPolicy::default(),
+            pinned_capacity: DEFAULT_PINNED_CACHE_SIZE,
+            eviction_listener: None,
+            stats: None,
         }
     }
 
@@ -28,6 +68,27 @@ impl InMemoryCacheBuilder {
         self
     }
 
+    /// Set the eviction policy of the pages.
+    ///
+    /// Default is [`EvictionPolicy::TinyLfu`].
+    pub fn eviction_policy(&mut self, policy: EvictionPolicy) -> &mut Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Weigh the pages with `weigher`, instead of by their size.
+    pub fn weigher(&mut self, weigher: PageWeigher) -> &mut Self {
+        self.weigher = Some(weigher);
+        self
+    }
+
+    /// Set the cost of loading a page again, used by
+    /// [`EvictionPolicy::GreedyDualSize`].
+    pub fn cost(&mut self, cost: PageCost) -> &mut Self {
+        self.cost = Some(cost);
+        self
+    }
+
     /// If an entry has been idle longer than `time_to_idle` seconds,
     /// it will be evicted.
     ///
@@ -37,8 +98,95 @@ impl InMemoryCacheBuilder {
         self
     }
 
+    /// Evict a page `ttl` after it was loaded, even if it is in use.
+    ///
+    /// Default is no limit.
+    pub fn time_to_live(&mut self, ttl: Duration) -> &mut Self {
+        self.time_to_live = Some(ttl);
+        self
+    }
+
+    /// Pick the time to live of the pages and metadata of each location,
+    /// overriding [`Self::time_to_live`] and [`Self::metadata_time_to_live`].
+    ///
+    /// Entries still expire once idle for longer than the time to idle.
+    pub fn expiry_policy(&mut self, policy: ExpiryPolicy) -> &mut Self {
+        self.expiry_policy = Some(policy);
+        self
+    }
+
+    /// Remember for `ttl` the locations whose loads failed with
+    /// [`Error::NotFound`](crate::Error::NotFound), and fail again without
+    /// calling the loader.
+    ///
+    /// The location is forgotten once it is put or invalidated. Disabled by default.
+    pub fn not_found_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.not_found_ttl = Some(ttl);
+        self
+    }
+
+    /// Set the capacity of the metadata cache, in bytes.
+    ///
+    /// It is not part of the page capacity. Default is 32 MB.
+    pub fn metadata_capacity(&mut self, capacity_bytes: usize) -> &mut Self {
+        self.metadata_capacity = capacity_bytes;
+        self
+    }
+
+    /// Evict the metadata of an object idle for longer than `tti`.
+    ///
+    /// Default is 30 minutes.
+    pub fn metadata_time_to_idle(&mut self, tti: Duration) -> &mut Self {
+        self.metadata_time_to_idle = tti;
+        self
+    }
+
+    /// Evict the metadata of an object `ttl` after it was loaded, even if
+    /// it is in use.
+    ///
+    /// Default is no limit.
+    pub fn metadata_time_to_live(&mut self, ttl: Duration) -> &mut Self {
+        self.metadata_time_to_live = Some(ttl);
+        self
+    }
+
+    /// Set the eviction policy of the metadata cache.
+    pub fn metadata_eviction_policy(&mut self, policy: EvictionPolicy) -> &mut Self {
+        self.metadata_eviction_policy = policy;
+        self
+    }
+
+    /// Set the capacity of the pinned pages, in bytes.
+    ///
+    /// It is not part of the page capacity, so that pinning does not evict
+    /// other pages. Default is 16 MB.
+    pub fn pinned_capacity(&mut self, capacity_bytes: usize) -> &mut Self {
+        self.pinned_capacity = capacity_bytes;
+        self
+    }
+
+    /// Be notified of the pages evicted from the cache.
+    ///
+    /// See [`TieredCache::with_demotion`](crate::tiered::TieredCache::with_demotion).
+    pub fn eviction_listener(&mut self, listener: EvictionListener) -> &mut Self {
+        self.eviction_listener = Some(listener);
+        self
+    }
+
+    /// Record the capacity and usage of the cache into `stats`.
+    ///
+    /// By default, each cache has its own [`AtomicIntCacheStats`].
+    pub fn stats(&mut self, stats: Arc<dyn CacheStats>) -> &mut Self {
+        self.stats = Some(stats);
+        self
+    }
+
     #[must_use]
     pub fn build(&self) -> InMemoryCache {
-        InMemoryCache::with_params(self.capacity, self.page_size, self.time_to_idle)
+        let stats = self
+            .stats
+            .clone()
+            .unwrap_or_else(|| Arc::new(AtomicIntCacheStats::new()));
+        InMemoryCache::with_params(self, stats)
     }
 }
@@ -0,0 +1,290 @@
+//! FIFO and S3-FIFO eviction
+//!
+//! moka only implements LRU and TinyLFU. With these policies, the moka cache
+//! is left unbounded and the entries to evict are picked here instead.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    sync::Mutex,
+};
+
+/// Share of the capacity used by the small queue of S3-FIFO.
+const SMALL_QUEUE_RATIO: u64 = 10;
+
+/// Accesses counted per entry, at most.
+const MAX_FREQUENCY: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Queue {
+    Small,
+    Main,
+}
+
+#[derive(Debug)]
+struct Entry {
+    weight: u64,
+
+    /// Accesses since the entry was inserted or last moved.
+    frequency: u8,
+    queue: Queue,
+
+    /// Position in its queue. Older positions of the same key are stale.
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct State<K> {
+    entries: HashMap<K, Entry>,
+
+    /// Oldest entries in front.
+    small: VecDeque<(K, u64)>,
+    main: VecDeque<(K, u64)>,
+
+    small_size: u64,
+    main_size: u64,
+
+    /// Keys recently evicted from the small queue, admitted straight into
+    /// the main queue if inserted again.
+    ghost: VecDeque<K>,
+    ghosts: HashSet<K>,
+
+    /// Keys picked for eviction, not removed from the cache yet.
+    evicted: HashSet<K>,
+
+    next_seq: u64,
+}
+
+/// Picks the entries to evict from a cache of `capacity` bytes.
+///
+/// With plain FIFO, entries are evicted in insertion order. With S3-FIFO, new
+/// entries go through a small queue first. Those accessed again before they
+/// reach its end are moved to the main queue, the others are evicted, so that
+/// one-time reads do not push out the working set.
+#[derive(Debug)]
+pub(super) struct FifoPolicy<K> {
+    capacity: u64,
+    s3: bool,
+    state: Mutex<State<K>>,
+}
+
+impl<K: Hash + Eq + Clone> FifoPolicy<K> {
+    pub(super) fn new(capacity: u64, s3: bool) -> Self {
+        Self {
+            capacity,
+            s3,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                small: VecDeque::new(),
+                main: VecDeque::new(),
+                small_size: 0,
+                main_size: 0,
+                ghost: VecDeque::new(),
+                ghosts: HashSet::new(),
+                evicted: HashSet::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    /// Record the insertion of `key`, and return the keys to evict.
+    ///
+    /// Each of them must then be removed from the cache, and reported to
+    /// [`Self::on_remove`].
+    pub(super) fn on_insert(&self, key: K, weight: u64) -> Vec<K> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        if let Some(entry) = state.entries.get_mut(&key) {
+            // Replaced, it keeps its position.
+            let size = match entry.queue {
+                Queue::Small => &mut state.small_size,
+                Queue::Main => &mut state.main_size,
+            };
+            *size = *size - entry.weight + weight;
+            entry.weight = weight;
+        } else {
+            let queue = if !self.s3 || state.ghosts.remove(&key) {
+                Queue::Main
+            } else {
+                Queue::Small
+            };
+            state.push(key, weight, 0, queue);
+        }
+
+        let mut victims = vec![];
+        while state.small_size + state.main_size > self.capacity {
+            let small_full = state.small_size > self.capacity / SMALL_QUEUE_RATIO;
+            let victim = if small_full || state.main_size == 0 {
+                state.evict_small().or_else(|| state.evict_main(self.s3))
+            } else {
+                state.evict_main(self.s3)
+            };
+            match victim {
+                Some(victim) => victims.push(victim),
+                None => break,
+            }
+        }
+        victims
+    }
+
+    /// Record an access to `key`.
+    pub(super) fn on_access(&self, key: &K) {
+        if !self.s3 {
+            return;
+        }
+        if let Some(entry) = self.state.lock().unwrap().entries.get_mut(key) {
+            entry.frequency = std::cmp::min(entry.frequency + 1, MAX_FREQUENCY);
+        }
+    }
+
+    /// Record the removal of `key` from the cache, other than by replacement.
+    ///
+    /// Return whether it was evicted by this policy.
+    pub(super) fn on_remove(&self, key: &K) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.evicted.remove(key) {
+            return true;
+        }
+        if let Some(entry) = state.entries.remove(key) {
+            match entry.queue {
+                Queue::Small => state.small_size -= entry.weight,
+                Queue::Main => state.main_size -= entry.weight,
+            }
+        }
+        false
+    }
+}
+
+impl<K: Hash + Eq + Clone> State<K> {
+    fn push(&mut self, key: K, weight: u64, frequency: u8, queue: Queue) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match queue {
+            Queue::Small => {
+                self.small.push_back((key.clone(), seq));
+                self.small_size += weight;
+            }
+            Queue::Main => {
+                self.main.push_back((key.clone(), seq));
+                self.main_size += weight;
+            }
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                weight,
+                frequency,
+                queue,
+                seq,
+            },
+        );
+    }
+
+    /// Pop the oldest live entry of `queue`.
+    fn pop(&mut self, queue: Queue) -> Option<(K, Entry)> {
+        loop {
+            let (key, seq) = match queue {
+                Queue::Small => self.small.pop_front()?,
+                Queue::Main => self.main.pop_front()?,
+            };
+            let live = self
+                .entries
+                .get(&key)
+                .is_some_and(|entry| entry.seq == seq && entry.queue == queue);
+            if live {
+                let entry = self.entries.remove(&key).unwrap();
+                match queue {
+                    Queue::Small => self.small_size -= entry.weight,
+                    Queue::Main => self.main_size -= entry.weight,
+                }
+                return Some((key, entry));
+            }
+        }
+    }
+
+    /// Evict the oldest entry of the small queue that was not accessed again,
+    /// moving the others to the main queue.
+    fn evict_small(&mut self) -> Option<K> {
+        while let Some((key, entry)) = self.pop(Queue::Small) {
+            if entry.frequency > 0 {
+                self.push(key, entry.weight, 0, Queue::Main);
+                continue;
+            }
+            self.ghosts.insert(key.clone());
+            self.ghost.push_back(key.clone());
+            // Remember about as many keys as there are entries.
+            while self.ghost.len() > std::cmp::max(self.entries.len(), 1) {
+                if let Some(old) = self.ghost.pop_front() {
+                    self.ghosts.remove(&old);
+                }
+            }
+            self.evicted.insert(key.clone());
+            return Some(key);
+        }
+        None
+    }
+
+    /// Evict the oldest entry of the main queue. With S3-FIFO, entries
+    /// accessed since they were last considered get another round.
+    fn evict_main(&mut self, s3: bool) -> Option<K> {
+        while let Some((key, entry)) = self.pop(Queue::Main) {
+            if s3 && entry.frequency > 0 {
+                self.push(key, entry.weight, entry.frequency - 1, Queue::Main);
+                continue;
+            }
+            self.evicted.insert(key.clone());
+            return Some(key);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(policy: &FifoPolicy<u32>, key: u32) -> Vec<u32> {
+        let victims = policy.on_insert(key, 1);
+        for victim in &victims {
+            assert!(policy.on_remove(victim));
+        }
+        victims
+    }
+
+    #[test]
+    fn test_fifo() {
+        let policy = FifoPolicy::new(3, false);
+        for key in 0..3 {
+            assert!(insert(&policy, key).is_empty());
+        }
+        // Accesses do not matter.
+        policy.on_access(&0);
+        assert_eq!(insert(&policy, 3), vec![0]);
+        assert_eq!(insert(&policy, 4), vec![1]);
+
+        // Removed entries free their space.
+        assert!(!policy.on_remove(&2));
+        assert!(insert(&policy, 5).is_empty());
+    }
+
+    #[test]
+    fn test_s3_fifo() {
+        let policy = FifoPolicy::new(20, true);
+        for key in 0..20 {
+            assert!(insert(&policy, key).is_empty());
+        }
+        policy.on_access(&0);
+        policy.on_access(&1);
+
+        // A scan only evicts the entries not accessed again.
+        let evicted: Vec<u32> = (100..110).flat_map(|key| insert(&policy, key)).collect();
+        assert_eq!(evicted, (2..12).collect::<Vec<_>>());
+
+        // Keys evicted recently are admitted into the main queue, and
+        // outlive the next scan with the others.
+        assert_eq!(insert(&policy, 2), vec![12]);
+        let evicted: Vec<u32> = (200..240).flat_map(|key| insert(&policy, key)).collect();
+        assert_eq!(evicted.len(), 40);
+        assert!(!evicted.iter().any(|key| [0, 1, 2].contains(key)));
+    }
+}
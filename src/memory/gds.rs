@@ -0,0 +1,178 @@
+//! GreedyDual-Size eviction
+//!
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    sync::Mutex,
+};
+
+#[derive(Debug)]
+struct Entry {
+    weight: u64,
+    cost: f64,
+
+    /// Position in the queue.
+    priority: Priority,
+}
+
+/// Priority of an entry, then insertion order for entries of equal priority.
+type Priority = (u64, u64);
+
+#[derive(Debug)]
+struct State<K> {
+    entries: HashMap<K, Entry>,
+
+    /// Entries by ascending priority.
+    queue: BTreeMap<Priority, K>,
+
+    size: u64,
+
+    /// Priority of the last evicted entry, added to the priority of the
+    /// entries inserted or accessed since, so that entries not accessed for
+    /// long are eventually evicted whatever their cost.
+    inflation: f64,
+
+    /// Keys picked for eviction, not removed from the cache yet.
+    evicted: HashSet<K>,
+
+    next_seq: u64,
+}
+
+/// Picks the entries to evict from a cache of `capacity` bytes, keeping the
+/// entries that are the most expensive to load per byte.
+///
+/// Each entry has a priority of `inflation + cost / weight`, restored when it
+/// is accessed, and the entry of lowest priority is evicted first. See "Cost-
+/// Aware WWW Proxy Caching Algorithms", Cao and Irani.
+#[derive(Debug)]
+pub(super) struct GreedyDualSize<K> {
+    capacity: u64,
+    state: Mutex<State<K>>,
+}
+
+impl<K: Hash + Eq + Clone> GreedyDualSize<K> {
+    pub(super) fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State {
+                entries: HashMap::new(),
+                queue: BTreeMap::new(),
+                size: 0,
+                inflation: 0.0,
+                evicted: HashSet::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    /// Record the insertion of `key`, and return the keys to evict.
+    ///
+    /// Each of them must then be removed from the cache, and reported to
+    /// [`Self::on_remove`].
+    pub(super) fn on_insert(&self, key: K, weight: u64, cost: f64) -> Vec<K> {
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        state.insert(key, weight, cost);
+
+        let mut victims = vec![];
+        while state.size > self.capacity {
+            let Some(((priority, _), victim)) = state.queue.pop_first() else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&victim) {
+                state.size -= entry.weight;
+            }
+            state.inflation = f64::from_bits(priority);
+            state.evicted.insert(victim.clone());
+            victims.push(victim);
+        }
+        victims
+    }
+
+    /// Record an access to `key`, restoring its priority.
+    pub(super) fn on_access(&self, key: &K) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.remove(key) {
+            state.insert(key.clone(), entry.weight, entry.cost);
+        }
+    }
+
+    /// Record the removal of `key` from the cache, other than by replacement.
+    ///
+    /// Return whether it was evicted by this policy.
+    pub(super) fn on_remove(&self, key: &K) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.evicted.remove(key) {
+            return true;
+        }
+        state.remove(key);
+        false
+    }
+}
+
+impl<K: Hash + Eq + Clone> State<K> {
+    fn insert(&mut self, key: K, weight: u64, cost: f64) {
+        let priority = self.inflation + cost.max(0.0) / std::cmp::max(weight, 1) as f64;
+        // The bits of positive floats are in the same order as the floats.
+        let priority = (priority.to_bits(), self.next_seq);
+        self.next_seq += 1;
+        self.queue.insert(priority, key.clone());
+        self.size += weight;
+        self.entries.insert(
+            key,
+            Entry {
+                weight,
+                cost,
+                priority,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.queue.remove(&entry.priority);
+        self.size -= entry.weight;
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(policy: &GreedyDualSize<u32>, key: u32, weight: u64, cost: f64) -> Vec<u32> {
+        let victims = policy.on_insert(key, weight, cost);
+        for victim in &victims {
+            assert!(policy.on_remove(victim));
+        }
+        victims
+    }
+
+    #[test]
+    fn test_greedy_dual_size() {
+        let policy = GreedyDualSize::new(30);
+        // Remote pages cost more to load than local ones.
+        assert!(insert(&policy, 0, 10, 100.0).is_empty());
+        assert!(insert(&policy, 1, 10, 1.0).is_empty());
+        assert!(insert(&policy, 2, 10, 1.0).is_empty());
+        assert_eq!(insert(&policy, 3, 10, 1.0), vec![1]);
+
+        // Accesses restore the priority of an entry.
+        policy.on_access(&2);
+        assert_eq!(insert(&policy, 4, 10, 1.0), vec![3]);
+
+        // Cheap entries end up pushing out expensive ones that are not used.
+        let evicted: Vec<u32> = (5..500)
+            .flat_map(|key| insert(&policy, key, 10, 1.0))
+            .collect();
+        assert!(evicted.contains(&0));
+
+        // Larger entries cost less per byte.
+        let policy = GreedyDualSize::new(30);
+        assert!(insert(&policy, 0, 20, 2.0).is_empty());
+        assert!(insert(&policy, 1, 10, 2.0).is_empty());
+        assert_eq!(insert(&policy, 2, 10, 2.0), vec![0]);
+        assert!(!policy.on_remove(&5));
+    }
+}
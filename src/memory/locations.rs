@@ -0,0 +1,251 @@
+//! Path ids of the locations in the cache
+//!
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use object_store::path::Path;
+use tokio::sync::RwLock;
+
+use crate::paging::ObjectVersion;
+
+/// Approximate memory used by a location, besides its path.
+const ENTRY_OVERHEAD: usize = 96;
+
+/// A location and the version of the object its pages were read from.
+#[derive(Debug)]
+struct LocationEntry {
+    location: Path,
+
+    /// `None` until the version is known from [`PageCache::head`](crate::paging::PageCache::head).
+    version: Option<ObjectVersion>,
+
+    /// Number of pages and metadata of this location in the cache.
+    ///
+    /// Updated under the read lock of the table, the write lock is only taken
+    /// to add or remove a location.
+    entries: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct LocationTable {
+    /// Provide fast lookup of path id
+    ids: HashMap<Path, u64>,
+
+    /// Reverse lookup of path id
+    entries: HashMap<u64, LocationEntry>,
+}
+
+/// Assigns path ids to locations.
+///
+/// Each cached page and metadata entry holds a reference to its path id. A
+/// location is removed from the table as soon as it has no entries left in
+/// the cache, so the table does not grow with every path ever touched.
+#[derive(Debug, Default)]
+pub(super) struct Locations {
+    table: RwLock<LocationTable>,
+
+    /// Next location id to be assigned
+    next_id: AtomicU64,
+}
+
+/// Approximate memory used by the table entry of `location`.
+///
+/// It is part of the weight of each page and metadata of the location, so
+/// that the table counts against the capacity of the cache.
+pub(super) fn entry_size(location: &Path) -> usize {
+    // The path is stored in both directions.
+    2 * location.as_ref().len() + ENTRY_OVERHEAD
+}
+
+impl Locations {
+    /// Number of locations in the table.
+    #[cfg(test)]
+    pub(super) async fn len(&self) -> usize {
+        self.table.read().await.ids.len()
+    }
+
+    /// The path id of `location`, if it is in the table.
+    pub(super) async fn id(&self, location: &Path) -> Option<u64> {
+        self.table.read().await.ids.get(location).copied()
+    }
+
+    /// The path id of `location`, assigning a new one if needed.
+    ///
+    /// A new location stays in the table until [`Self::release_if_unused`]
+    /// is called, or its first entry is released.
+    pub(super) async fn get_or_insert(&self, location: &Path) -> u64 {
+        if let Some(id) = self.id(location).await {
+            return id;
+        }
+
+        let mut table = self.table.write().await;
+        // on lock-escalation, check if someone else has added it
+        if let Some(&id) = table.ids.get(location) {
+            return id;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.insert(&mut table, location, id, None, 0);
+        id
+    }
+
+    fn insert(
+        &self,
+        table: &mut LocationTable,
+        location: &Path,
+        id: u64,
+        version: Option<ObjectVersion>,
+        entries: usize,
+    ) {
+        table.ids.insert(location.clone(), id);
+        table.entries.insert(
+            id,
+            LocationEntry {
+                location: location.clone(),
+                version,
+                entries: AtomicUsize::new(entries),
+            },
+        );
+    }
+
+    fn remove_entry(&self, table: &mut LocationTable, id: u64) {
+        if let Some(entry) = table.entries.remove(&id) {
+            if table.ids.get(&entry.location) == Some(&id) {
+                table.ids.remove(&entry.location);
+            }
+        }
+    }
+
+    /// The location of a path id.
+    pub(super) async fn location(&self, id: u64) -> Option<Path> {
+        let table = self.table.read().await;
+        table.entries.get(&id).map(|entry| entry.location.clone())
+    }
+
+    /// Remove `location` from the table, returning its path id.
+    ///
+    /// Entries of the returned id still in the cache are unreachable.
+    pub(super) async fn remove(&self, location: &Path) -> Option<u64> {
+        let mut table = self.table.write().await;
+        let id = *table.ids.get(location)?;
+        self.remove_entry(&mut table, id);
+        Some(id)
+    }
+
+    /// Record that an entry of `id` was inserted into the cache.
+    pub(super) async fn acquire(&self, id: u64) {
+        if let Some(entry) = self.table.read().await.entries.get(&id) {
+            entry.entries.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Record that an entry of `id` was removed from the cache.
+    pub(super) async fn release(&self, id: u64) {
+        let released = self
+            .table
+            .read()
+            .await
+            .entries
+            .get(&id)
+            .is_some_and(|entry| {
+                entry
+                    .entries
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                    == Ok(1)
+            });
+        if released {
+            self.release_if_unused(id).await;
+        }
+    }
+
+    /// Remove `id` if it has no entries in the cache, e.g. after a failed load.
+    pub(super) async fn release_if_unused(&self, id: u64) {
+        let mut table = self.table.write().await;
+        // An entry may have been acquired since the last one was released.
+        if table
+            .entries
+            .get(&id)
+            .is_some_and(|entry| entry.entries.load(Ordering::Acquire) == 0)
+        {
+            self.remove_entry(&mut table, id);
+        }
+    }
+
+    /// Bind the entries of `id` to `version`.
+    ///
+    /// Returns the new path id of `location` if its entries belong to a
+    /// different version. The new id holds one reference, for the metadata
+    /// of the new version.
+    pub(super) async fn bind_version(
+        &self,
+        location: &Path,
+        id: u64,
+        version: ObjectVersion,
+    ) -> Option<u64> {
+        {
+            let table = self.table.read().await;
+            if table.entries.get(&id)?.version.as_ref() == Some(&version) {
+                return None;
+            }
+        }
+
+        let mut table = self.table.write().await;
+        if table.ids.get(location) != Some(&id) {
+            // Invalidated, or already moved by a concurrent call.
+            return None;
+        }
+        let entry = table.entries.get_mut(&id)?;
+        match &entry.version {
+            Some(v) if *v == version => return None,
+            Some(_) => {}
+            None => {
+                entry.version = Some(version);
+                return None;
+            }
+        }
+
+        self.remove_entry(&mut table, id);
+        let new_id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.insert(&mut table, location, new_id, Some(version), 1);
+        Some(new_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_references() {
+        let locations = Arc::new(Locations::default());
+        let location = Path::from("some/file.lance");
+        let id = locations.get_or_insert(&location).await;
+        locations.acquire(id).await;
+
+        let tasks = (0..8)
+            .map(|_| {
+                let locations = locations.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        locations.acquire(id).await;
+                        locations.release(id).await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // Still referenced by its first entry.
+        assert_eq!(locations.id(&location).await, Some(id));
+
+        locations.release(id).await;
+        assert_eq!(locations.id(&location).await, None);
+        assert_eq!(locations.len().await, 0);
+    }
+}
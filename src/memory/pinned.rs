@@ -0,0 +1,294 @@
+//! Pinned pages
+//!
+//! Pinned pages are kept out of the moka cache, so that neither its eviction
+//! policy nor its expiry ever removes them.
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use bytes::Bytes;
+use object_store::path::Path;
+
+use crate::stats::CacheStats;
+
+/// Outcome of [`Pinned::insert`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Insertion {
+    /// The page is not pinned, or does not fit.
+    Rejected,
+    Added,
+    Replaced,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Pinned page ranges of each location, sorted and disjoint.
+    ranges: HashMap<Path, Vec<Range<u32>>>,
+
+    /// Data of the pinned pages, by `(path id, page id)`.
+    pages: HashMap<(u64, u32), Bytes>,
+
+    size: u64,
+}
+
+impl State {
+    fn is_pinned(&self, location: &Path, page_id: u32) -> bool {
+        self.ranges.get(location).is_some_and(|ranges| {
+            let i = ranges.partition_point(|range| range.end <= page_id);
+            ranges.get(i).is_some_and(|range| range.contains(&page_id))
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.pages.is_empty()
+    }
+}
+
+/// Add `range` to the sorted and disjoint `ranges`, merging those it overlaps
+/// or touches.
+fn merge(ranges: &mut Vec<Range<u32>>, range: Range<u32>) {
+    let first = ranges.partition_point(|r| r.end < range.start);
+    let last = ranges.partition_point(|r| r.start <= range.end);
+    let merged = ranges[first..last].iter().fold(range, |merged, r| {
+        std::cmp::min(merged.start, r.start)..std::cmp::max(merged.end, r.end)
+    });
+    ranges.splice(first..last, [merged]);
+}
+
+/// Pinned page ranges, and the data of those cached, up to `capacity` bytes.
+#[derive(Debug)]
+pub(super) struct Pinned {
+    capacity: u64,
+    state: Mutex<State>,
+
+    /// Whether nothing is pinned, so that the lookups of the pages that are
+    /// not pinned skip the lock.
+    empty: AtomicBool,
+
+    stats: Arc<dyn CacheStats>,
+}
+
+impl Pinned {
+    pub(super) fn new(capacity: u64, stats: Arc<dyn CacheStats>) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State::default()),
+            empty: AtomicBool::new(true),
+            stats,
+        }
+    }
+
+    /// Lock the state, unless nothing is pinned.
+    fn lock(&self) -> Option<MutexGuard<'_, State>> {
+        if self.empty.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(self.state.lock().unwrap())
+    }
+
+    /// Update [`Self::empty`] once `state` changed.
+    fn update(&self, state: &State) {
+        self.empty.store(state.is_empty(), Ordering::Release);
+    }
+
+    /// Bytes of the pinned pages.
+    pub(super) fn size(&self) -> u64 {
+        self.lock().map_or(0, |state| state.size)
+    }
+
+    pub(super) fn pin(&self, location: &Path, pages: Range<u32>) {
+        if pages.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        merge(state.ranges.entry(location.clone()).or_default(), pages);
+        self.update(&state);
+    }
+
+    /// Unpin `pages` of `location`, and return the pages of `location_id`
+    /// no longer pinned.
+    pub(super) fn unpin(
+        &self,
+        location: &Path,
+        location_id: Option<u64>,
+        pages: Range<u32>,
+    ) -> Vec<((u64, u32), Bytes)> {
+        let Some(mut state) = self.lock() else {
+            return vec![];
+        };
+        let Some(ranges) = state.ranges.remove(location) else {
+            return vec![];
+        };
+        let ranges = ranges
+            .into_iter()
+            .flat_map(|range| {
+                [
+                    range.start..std::cmp::min(range.end, pages.start),
+                    std::cmp::max(range.start, pages.end)..range.end,
+                ]
+            })
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        if !ranges.is_empty() {
+            state.ranges.insert(location.clone(), ranges);
+        }
+        self.update(&state);
+
+        let Some(location_id) = location_id else {
+            return vec![];
+        };
+        let unpinned = state
+            .pages
+            .keys()
+            .filter(|&&(id, page_id)| id == location_id && !state.is_pinned(location, page_id))
+            .copied()
+            .collect::<Vec<_>>();
+        let unpinned = unpinned
+            .into_iter()
+            .filter_map(|key| {
+                let data = state.pages.remove(&key)?;
+                state.size -= data.len() as u64;
+                self.stats.sub_pinned_usage(data.len() as u64);
+                Some((key, data))
+            })
+            .collect();
+        self.update(&state);
+        unpinned
+    }
+
+    #[cfg(test)]
+    pub(super) fn is_pinned(&self, location: &Path, page_id: u32) -> bool {
+        self.lock()
+            .is_some_and(|state| state.is_pinned(location, page_id))
+    }
+
+    pub(super) fn get(&self, key: &(u64, u32)) -> Option<Bytes> {
+        self.lock()?.pages.get(key).cloned()
+    }
+
+    pub(super) fn contains(&self, key: &(u64, u32)) -> bool {
+        self.lock()
+            .is_some_and(|state| state.pages.contains_key(key))
+    }
+
+    /// Keep `data` at `key`, if its page of `location` is pinned and the
+    /// pinned pages still fit in the capacity.
+    pub(super) fn insert(&self, location: &Path, key: (u64, u32), data: &Bytes) -> Insertion {
+        let Some(mut state) = self.lock() else {
+            return Insertion::Rejected;
+        };
+        if !state.is_pinned(location, key.1) {
+            return Insertion::Rejected;
+        }
+        let replaced = state.pages.get(&key).map_or(0, |old| old.len() as u64);
+        if state.size - replaced + data.len() as u64 > self.capacity {
+            return Insertion::Rejected;
+        }
+        state.size = state.size - replaced + data.len() as u64;
+        self.stats.sub_pinned_usage(replaced);
+        self.stats.inc_pinned_usage(data.len() as u64);
+        match state.pages.insert(key, data.clone()) {
+            Some(_) => Insertion::Replaced,
+            None => {
+                self.update(&state);
+                Insertion::Added
+            }
+        }
+    }
+
+    /// Drop the pages of `location_id`, and return how many there were.
+    pub(super) fn remove(&self, location_id: u64) -> usize {
+        let Some(mut state) = self.lock() else {
+            return 0;
+        };
+        let mut removed = 0;
+        let mut size = 0;
+        state.pages.retain(|key, data| {
+            if key.0 != location_id {
+                return true;
+            }
+            removed += 1;
+            size += data.len() as u64;
+            false
+        });
+        state.size -= size;
+        self.stats.sub_pinned_usage(size);
+        self.update(&state);
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::stats::{AtomicIntCacheStats, CacheCapacityStats};
+
+    #[test]
+    fn test_pinned() {
+        let stats = Arc::new(AtomicIntCacheStats::new());
+        let pinned = Pinned::new(32, stats.clone());
+        let location = Path::from("some/file.lance");
+        let data = Bytes::from(vec![1; 10]);
+
+        assert_eq!(pinned.insert(&location, (1, 0), &data), Insertion::Rejected);
+        pinned.pin(&location, 0..2);
+        pinned.pin(&location, 4..u32::MAX);
+        assert_eq!(pinned.insert(&location, (1, 0), &data), Insertion::Added);
+        assert_eq!(pinned.insert(&location, (1, 0), &data), Insertion::Replaced);
+        assert_eq!(pinned.insert(&location, (1, 2), &data), Insertion::Rejected);
+        assert_eq!(pinned.insert(&location, (1, 1), &data), Insertion::Added);
+        assert_eq!(pinned.insert(&location, (1, 5), &data), Insertion::Added);
+        // Over capacity.
+        assert_eq!(pinned.insert(&location, (1, 6), &data), Insertion::Rejected);
+        assert_eq!(pinned.size(), 30);
+        assert_eq!(stats.pinned_usage(), 30);
+
+        let unpinned = pinned.unpin(&location, Some(1), 1..5);
+        assert_eq!(unpinned, vec![((1, 1), data.clone())]);
+        assert!(pinned.is_pinned(&location, 0));
+        assert!(!pinned.is_pinned(&location, 4));
+        assert!(pinned.is_pinned(&location, 5));
+        assert_eq!(stats.pinned_usage(), 20);
+
+        assert_eq!(pinned.remove(1), 2);
+        assert!(pinned.get(&(1, 0)).is_none());
+        assert_eq!(pinned.size(), 0);
+        assert_eq!(stats.pinned_usage(), 0);
+        // Pages loaded again are pinned again.
+        assert_eq!(pinned.insert(&location, (2, 0), &data), Insertion::Added);
+        assert!(pinned.contains(&(2, 0)));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut ranges = vec![];
+        for range in [4..6, 0..2, 8..10, 2..3, 5..8, 12..14] {
+            merge(&mut ranges, range);
+        }
+        assert_eq!(ranges, vec![0..3, 4..10, 12..14]);
+        merge(&mut ranges, 1..13);
+        assert_eq!(ranges, vec![0..14]);
+    }
+
+    #[test]
+    fn test_empty() {
+        let pinned = Pinned::new(32, Arc::new(AtomicIntCacheStats::new()));
+        let location = Path::from("some/file.lance");
+        assert!(pinned.empty.load(Ordering::Acquire));
+        pinned.pin(&location, 0..2);
+        assert!(!pinned.empty.load(Ordering::Acquire));
+        pinned.unpin(&location, None, 0..2);
+        assert!(pinned.empty.load(Ordering::Acquire));
+        assert_eq!(
+            pinned.insert(&location, (1, 0), &Bytes::new()),
+            Insertion::Rejected
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Eviction and expiry policies of the in-memory cache
+//!
+
+use std::{
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use object_store::{path::Path, ObjectMeta};
+
+use super::{fifo::FifoPolicy, gds::GreedyDualSize, locations::entry_size, Page};
+
+/// Load time assumed for the entries inserted without a loader.
+const DEFAULT_LOAD_TIME: Duration = Duration::from_millis(10);
+
+/// Picks how long the pages and metadata of a location may stay in an
+/// [`InMemoryCache`](super::InMemoryCache) after they are loaded, whether
+/// they are used or not. `None` means they only expire when idle.
+///
+/// ```
+/// # use std::{sync::Arc, time::Duration};
+/// use ocra::memory::InMemoryCache;
+///
+/// let cache = InMemoryCache::builder(64 * 1024 * 1024)
+///     .expiry_policy(Arc::new(|location| {
+///         location
+///             .filename()
+///             .is_some_and(|name| name.ends_with(".manifest"))
+///             .then_some(Duration::from_secs(5))
+///     }))
+///     .build();
+/// ```
+pub type ExpiryPolicy = Arc<dyn Fn(&Path) -> Option<Duration> + Send + Sync>;
+
+/// Weight of a page of an [`InMemoryCache`](super::InMemoryCache), counted
+/// against its capacity. By default, the size of the page in bytes, plus the
+/// memory used to look up its path.
+pub type PageWeigher = Arc<dyn Fn(&Path, &Bytes) -> u32 + Send + Sync>;
+
+/// Cost of loading a page of an [`InMemoryCache`](super::InMemoryCache)
+/// again, for [`EvictionPolicy::GreedyDualSize`], given how long it took to
+/// load, or `None` if the page was put.
+///
+/// The load time is the latency passed to
+/// [`PageCache::get_with_latency`](crate::paging::PageCache::get_with_latency),
+/// such as the latency of the inner read of a
+/// [`ReadThroughCache`](crate::ReadThroughCache), or else the time spent in
+/// the loader.
+///
+/// By default, the load time in seconds, and 10 ms for the pages put.
+pub type PageCost = Arc<dyn Fn(&Path, &Bytes, Option<Duration>) -> f64 + Send + Sync>;
+
+/// How an [`InMemoryCache`](super::InMemoryCache) picks the entries to evict
+/// once full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Evict the least recently used entry.
+    Lru,
+
+    /// Only admit a new entry if it is used more often than the entries it
+    /// would evict, then evict the least recently used.
+    #[default]
+    TinyLfu,
+
+    /// Evict entries in insertion order, whether they are used or not.
+    Fifo,
+
+    /// Evict new entries not used again soon after being inserted first,
+    /// then the others in insertion order, skipping those used since.
+    ///
+    /// Keeps the working set through large scans. See
+    /// <https://s3fifo.com>.
+    S3Fifo,
+
+    /// Evict the entries that are the cheapest to load again per byte,
+    /// unless they were used more recently than the others were evicted.
+    ///
+    /// The cost of a page is its [`PageCost`], by default how long it took
+    /// to load, such as the latency of a miss of a
+    /// [`ReadThroughCache`](crate::ReadThroughCache).
+    GreedyDualSize,
+}
+
+impl EvictionPolicy {
+    /// The moka policy, or `None` if the entries are evicted by an [`Evictor`].
+    pub(super) fn to_moka(self) -> Option<moka::policy::EvictionPolicy> {
+        match self {
+            Self::Lru => Some(moka::policy::EvictionPolicy::lru()),
+            Self::TinyLfu => Some(moka::policy::EvictionPolicy::tiny_lfu()),
+            Self::Fifo | Self::S3Fifo | Self::GreedyDualSize => None,
+        }
+    }
+
+    pub(super) fn evictor<K: Hash + Eq + Clone>(self, capacity: u64) -> Option<Arc<Evictor<K>>> {
+        let evictor = match self {
+            Self::Lru | Self::TinyLfu => return None,
+            Self::Fifo => Evictor::Fifo(FifoPolicy::new(capacity, false)),
+            Self::S3Fifo => Evictor::Fifo(FifoPolicy::new(capacity, true)),
+            Self::GreedyDualSize => Evictor::GreedyDualSize(GreedyDualSize::new(capacity)),
+        };
+        Some(Arc::new(evictor))
+    }
+}
+
+/// Picks the entries to evict with the policies moka does not implement.
+#[derive(Debug)]
+pub(super) enum Evictor<K> {
+    Fifo(FifoPolicy<K>),
+    GreedyDualSize(GreedyDualSize<K>),
+}
+
+impl<K: Hash + Eq + Clone> Evictor<K> {
+    /// Record the insertion of `key`, and return the keys to evict.
+    ///
+    /// Each of them must then be removed from the cache, and reported to
+    /// [`Self::on_remove`].
+    pub(super) fn on_insert(&self, key: K, weight: u64, cost: f64) -> Vec<K> {
+        match self {
+            Self::Fifo(policy) => policy.on_insert(key, weight),
+            Self::GreedyDualSize(policy) => policy.on_insert(key, weight, cost),
+        }
+    }
+
+    /// Record an access to `key`.
+    pub(super) fn on_access(&self, key: &K) {
+        match self {
+            Self::Fifo(policy) => policy.on_access(key),
+            Self::GreedyDualSize(policy) => policy.on_access(key),
+        }
+    }
+
+    /// Record the removal of `key` from the cache, other than by replacement.
+    ///
+    /// Return whether it was evicted by this policy.
+    pub(super) fn on_remove(&self, key: &K) -> bool {
+        match self {
+            Self::Fifo(policy) => policy.on_remove(key),
+            Self::GreedyDualSize(policy) => policy.on_remove(key),
+        }
+    }
+}
+
+/// Weight and cost of the pages.
+pub(super) struct PageWeights {
+    pub(super) weigher: Option<PageWeigher>,
+    pub(super) cost: Option<PageCost>,
+}
+
+impl PageWeights {
+    pub(super) fn weight(&self, location: &Path, data: &Bytes) -> u32 {
+        match &self.weigher {
+            Some(weigher) => weigher(location, data),
+            None => (data.len() + entry_size(location)) as u32,
+        }
+    }
+
+    pub(super) fn cost(&self, location: &Path, data: &Bytes, load_time: Option<Duration>) -> f64 {
+        match &self.cost {
+            Some(cost) => cost(location, data, load_time),
+            None => load_cost(load_time),
+        }
+    }
+}
+
+impl std::fmt::Debug for PageWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PageWeights")
+            .field("weigher", &self.weigher.as_ref().map(|_| "<weigher>"))
+            .field("cost", &self.cost.as_ref().map(|_| "<cost>"))
+            .finish()
+    }
+}
+
+/// Default cost of an entry loaded in `load_time`.
+pub(super) fn load_cost(load_time: Option<Duration>) -> f64 {
+    load_time.unwrap_or(DEFAULT_LOAD_TIME).as_secs_f64()
+}
+
+/// Expires each page after the time to live resolved when it was inserted.
+pub(super) struct PageExpiry;
+
+impl moka::Expiry<(u64, u32), Page> for PageExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &(u64, u32),
+        page: &Page,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        page.time_to_live
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &(u64, u32),
+        page: &Page,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        page.time_to_live
+    }
+}
+
+/// Resolves the time to live of the entries of a location.
+pub(super) struct TimeToLive {
+    pub(super) policy: Option<ExpiryPolicy>,
+
+    /// Used without a policy.
+    pub(super) default: Option<Duration>,
+}
+
+impl TimeToLive {
+    pub(super) fn of(&self, location: &Path) -> Option<Duration> {
+        match &self.policy {
+            Some(policy) => policy(location),
+            None => self.default,
+        }
+    }
+}
+
+impl std::fmt::Debug for TimeToLive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimeToLive")
+            .field("policy", &self.policy.as_ref().map(|_| "<policy>"))
+            .field("default", &self.default)
+            .finish()
+    }
+}
+
+/// Expires object metadata after the time to live of their location.
+pub(super) struct MetadataExpiry(pub(super) TimeToLive);
+
+impl moka::Expiry<u64, ObjectMeta> for MetadataExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &u64,
+        meta: &ObjectMeta,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        self.0.of(&meta.location)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &u64,
+        meta: &ObjectMeta,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.0.of(&meta.location)
+    }
+}
@@ -0,0 +1,428 @@
+//! OpenMetrics exporter for cache stats
+//!
+//! Renders [`CacheStats`] and the state of a [`PageCache`] in the
+//! [OpenMetrics](https://openmetrics.io) text format, ready to be served
+//! from a Prometheus scrape endpoint.
+//!
+//! ```
+//! use ocra::{memory::InMemoryCache, openmetrics::OpenMetricsExporter, paging::PageCache};
+//!
+//! let cache = InMemoryCache::new(1024 * 1024, 4096);
+//! let exporter = OpenMetricsExporter::new("ocra").label("service", "search");
+//! let text = exporter.render(&cache);
+//! assert!(text.contains("ocra_reads_total{service=\"search\"} 0"));
+//! ```
+
+use std::fmt::Write;
+
+use crate::{
+    paging::PageCache,
+    stats::{CacheStats, HistogramSnapshot, ReadOutcome},
+};
+
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+/// Labels set by the exporter on some samples.
+const RESERVED_LABELS: &[&str] = &["le", "result"];
+
+/// Renders cache metrics in the OpenMetrics text format.
+///
+/// All metric names are prefixed with the configured prefix, and every
+/// sample carries the configured labels. Only the prefix is configurable,
+/// the rest of each metric name is fixed.
+///
+/// Characters not allowed in metric and label names are replaced with `_`.
+/// Label names reserved by OpenMetrics, or used by the exporter itself
+/// (`le` and `result`), are prefixed with `exported_`. Label values are
+/// escaped.
+#[derive(Debug, Clone)]
+pub struct OpenMetricsExporter {
+    prefix: String,
+    labels: Vec<(String, String)>,
+}
+
+impl OpenMetricsExporter {
+    /// Create an exporter whose metric names start with `prefix`.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        Self {
+            prefix: if prefix.is_empty() {
+                prefix
+            } else {
+                sanitize_name(&prefix)
+            },
+            labels: vec![],
+        }
+    }
+
+    /// Add a label to every sample, replacing any label of the same name.
+    pub fn label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let mut name = sanitize_name(&name.into());
+        if name.starts_with("__") || RESERVED_LABELS.contains(&name.as_str()) {
+            name.insert_str(0, "exported_");
+        }
+        self.labels.retain(|(label, _)| *label != name);
+        self.labels.push((name, value.into()));
+        self
+    }
+
+    /// Render the stats and state of `cache`, terminated by `# EOF`.
+    pub fn render<C: PageCache>(&self, cache: &C) -> String {
+        let mut out = String::new();
+        self.encode(&mut out, cache);
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Append the metrics of `cache` to `out`, without the `# EOF` terminator.
+    ///
+    /// Useful to export several caches from one endpoint, each with its own
+    /// exporter labels.
+    pub fn encode<C: PageCache>(&self, out: &mut String, cache: &C) {
+        self.encode_stats(out, cache.stats().as_ref());
+        self.gauge(
+            out,
+            "page_size",
+            "bytes",
+            "Size of a cache page.",
+            cache.page_size() as f64,
+        );
+        self.gauge(
+            out,
+            "size",
+            "bytes",
+            "Memory or disk used by the cache, including its index.",
+            cache.size() as f64,
+        );
+    }
+
+    /// Append the metrics of `stats` to `out`, without the `# EOF` terminator.
+    pub fn encode_stats(&self, out: &mut String, stats: &dyn CacheStats) {
+        let reads = stats.total_reads();
+        let misses = stats.total_misses();
+        self.counter(out, "reads", "", "Total page reads.", reads as f64);
+        self.counter(
+            out,
+            "misses",
+            "",
+            "Total page reads missing the cache.",
+            misses as f64,
+        );
+        let hit_ratio = if reads == 0 {
+            0.0
+        } else {
+            reads.saturating_sub(misses) as f64 / reads as f64
+        };
+        self.gauge(
+            out,
+            "hit_ratio",
+            "",
+            "Ratio of page reads served by the cache.",
+            hit_ratio,
+        );
+        self.counter(
+            out,
+            "prefetches",
+            "",
+            "Total pages loaded ahead of being read.",
+            stats.total_prefetches() as f64,
+        );
+        self.counter(
+            out,
+            "admitted",
+            "",
+            "Total missing pages inserted after passing the admission filter.",
+            stats.total_admitted() as f64,
+        );
+        self.counter(
+            out,
+            "rejected",
+            "",
+            "Total missing pages served without being inserted by the admission filter.",
+            stats.total_rejected() as f64,
+        );
+        self.counter(
+            out,
+            "evictions",
+            "",
+            "Total pages evicted or expired.",
+            stats.total_evictions() as f64,
+        );
+        self.gauge(
+            out,
+            "capacity",
+            "bytes",
+            "Capacity of the cache.",
+            stats.max_capacity() as f64,
+        );
+        self.gauge(
+            out,
+            "usage",
+            "bytes",
+            "Bytes of pages cached.",
+            stats.usage() as f64,
+        );
+        self.gauge(
+            out,
+            "metadata_usage",
+            "bytes",
+            "Memory used by cached object metadata.",
+            stats.metadata_usage() as f64,
+        );
+        self.gauge(
+            out,
+            "pinned_usage",
+            "bytes",
+            "Bytes of the pinned pages.",
+            stats.pinned_usage() as f64,
+        );
+
+        let name = self.name("read_duration", "seconds");
+        self.header(
+            out,
+            &name,
+            "histogram",
+            "seconds",
+            "Latency of range reads, by how much of the range was cached.",
+        );
+        for (outcome, label) in [
+            (ReadOutcome::Hit, "hit"),
+            (ReadOutcome::Miss, "miss"),
+            (ReadOutcome::PartialHit, "partial_hit"),
+        ] {
+            let histogram = stats.read_latency(outcome);
+            self.histogram(out, &name, &[("result", label)], &histogram, NANOS_PER_SEC);
+        }
+
+        let name = self.name("fetch_duration", "seconds");
+        self.header(
+            out,
+            &name,
+            "histogram",
+            "seconds",
+            "Latency of the reads issued to the inner store.",
+        );
+        self.histogram(out, &name, &[], &stats.fetch_latency(), NANOS_PER_SEC);
+
+        let name = self.name("fetch_size", "bytes");
+        self.header(
+            out,
+            &name,
+            "histogram",
+            "bytes",
+            "Size of the reads issued to the inner store.",
+        );
+        self.histogram(out, &name, &[], &stats.fetch_size(), 1.0);
+    }
+
+    fn name(&self, name: &str, unit: &str) -> String {
+        let mut full = String::with_capacity(self.prefix.len() + name.len() + unit.len() + 2);
+        if !self.prefix.is_empty() {
+            full.push_str(&self.prefix);
+            full.push('_');
+        }
+        full.push_str(name);
+        if !unit.is_empty() {
+            full.push('_');
+            full.push_str(unit);
+        }
+        full
+    }
+
+    fn header(&self, out: &mut String, name: &str, kind: &str, unit: &str, help: &str) {
+        writeln!(out, "# TYPE {name} {kind}").unwrap();
+        if !unit.is_empty() {
+            writeln!(out, "# UNIT {name} {unit}").unwrap();
+        }
+        writeln!(out, "# HELP {name} {help}").unwrap();
+    }
+
+    fn counter(&self, out: &mut String, name: &str, unit: &str, help: &str, value: f64) {
+        let name = self.name(name, unit);
+        self.header(out, &name, "counter", unit, help);
+        self.sample(out, &name, "_total", &[], value);
+    }
+
+    fn gauge(&self, out: &mut String, name: &str, unit: &str, help: &str, value: f64) {
+        let name = self.name(name, unit);
+        self.header(out, &name, "gauge", unit, help);
+        self.sample(out, &name, "", &[], value);
+    }
+
+    /// Write the samples of `histogram`, whose values are divided by `scale`.
+    fn histogram(
+        &self,
+        out: &mut String,
+        name: &str,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+        scale: f64,
+    ) {
+        let mut cumulative = 0;
+        for (i, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let le = histogram
+                .bounds
+                .get(i)
+                .map_or("+Inf".to_string(), |&bound| {
+                    (bound as f64 / scale).to_string()
+                });
+            let bucket_labels = labels
+                .iter()
+                .copied()
+                .chain([("le", le.as_str())])
+                .collect::<Vec<_>>();
+            self.sample(out, name, "_bucket", &bucket_labels, cumulative as f64);
+        }
+        self.sample(out, name, "_count", labels, cumulative as f64);
+        self.sample(out, name, "_sum", labels, histogram.sum as f64 / scale);
+    }
+
+    fn sample(
+        &self,
+        out: &mut String,
+        name: &str,
+        suffix: &str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        out.push_str(name);
+        out.push_str(suffix);
+        let mut labels = self
+            .labels
+            .iter()
+            .map(|(label, value)| (label.as_str(), value.as_str()))
+            .chain(labels.iter().copied())
+            .peekable();
+        if labels.peek().is_some() {
+            out.push('{');
+            for (i, (label, value)) in labels.enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write!(out, "{label}=\"").unwrap();
+                escape_label_value(out, value);
+                out.push('"');
+            }
+            out.push('}');
+        }
+        writeln!(out, " {value}").unwrap();
+    }
+}
+
+/// Replace the characters not allowed in metric and label names with `_`.
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    if !sanitized.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label_value(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use object_store::path::Path;
+
+    use crate::memory::InMemoryCache;
+
+    #[tokio::test]
+    async fn test_render() {
+        let cache = InMemoryCache::new(1024, 16);
+        let location = Path::from("a");
+        cache
+            .get_with(&location, 0, async { Ok(Bytes::from_static(&[0; 16])) })
+            .await
+            .unwrap();
+        let stats = cache.stats();
+        stats.inc_total_reads();
+        stats.inc_total_reads();
+        stats.inc_total_misses();
+        stats.record_read_latency(ReadOutcome::Miss, Duration::from_millis(3));
+        stats.record_fetch(Duration::from_millis(2), 16);
+
+        let exporter = OpenMetricsExporter::new("ocra").label("service", "search");
+        let text = exporter.render(&cache);
+
+        assert!(text.contains("# TYPE ocra_reads counter\n"));
+        assert!(text.contains("ocra_reads_total{service=\"search\"} 2\n"));
+        assert!(text.contains("ocra_misses_total{service=\"search\"} 1\n"));
+        assert!(text.contains("ocra_hit_ratio{service=\"search\"} 0.5\n"));
+        assert!(text.contains("# UNIT ocra_usage_bytes bytes\n"));
+        assert!(text.contains("ocra_usage_bytes{service=\"search\"} 16\n"));
+        assert!(text.contains("ocra_capacity_bytes{service=\"search\"} 1024\n"));
+        assert!(text.contains("ocra_metadata_usage_bytes{service=\"search\"} 0\n"));
+        assert!(text.contains("ocra_pinned_usage_bytes{service=\"search\"} 0\n"));
+        assert!(text.contains("ocra_page_size_bytes{service=\"search\"} 16\n"));
+
+        assert!(text.contains("# TYPE ocra_read_duration_seconds histogram\n"));
+        for expected in [
+            "ocra_read_duration_seconds_bucket{service=\"search\",result=\"miss\",le=\"0.0025\"} 0\n",
+            "ocra_read_duration_seconds_bucket{service=\"search\",result=\"miss\",le=\"0.005\"} 1\n",
+            "ocra_read_duration_seconds_bucket{service=\"search\",result=\"miss\",le=\"+Inf\"} 1\n",
+            "ocra_read_duration_seconds_sum{service=\"search\",result=\"miss\"} 0.003\n",
+            "ocra_read_duration_seconds_count{service=\"search\",result=\"hit\"} 0\n",
+            "ocra_fetch_duration_seconds_count{service=\"search\"} 1\n",
+            "ocra_fetch_size_bytes_bucket{service=\"search\",le=\"1024\"} 1\n",
+        ] {
+            assert!(text.contains(expected), "missing {expected}");
+        }
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_escape_labels() {
+        let cache = InMemoryCache::new(1024, 16);
+        let text = OpenMetricsExporter::new("ocra")
+            .label("tier", "a\"b\\c\nd")
+            .render(&cache);
+        assert!(text.contains(r#"ocra_reads_total{tier="a\"b\\c\nd"} 0"#));
+    }
+
+    #[test]
+    fn test_sanitize_names() {
+        let cache = InMemoryCache::new(1024, 16);
+        let text = OpenMetricsExporter::new("my-cache.v1")
+            .label("service name", "search")
+            .label("1st", "a")
+            .label("le", "b")
+            .label("__name__", "c")
+            .label("service_name", "index")
+            .render(&cache);
+        assert!(text.contains(
+            "my_cache_v1_reads_total{_1st=\"a\",exported_le=\"b\",\
+             exported___name__=\"c\",service_name=\"index\"} 0\n"
+        ));
+        assert!(text.contains("le=\"+Inf\"}"));
+    }
+
+    #[test]
+    fn test_no_prefix_no_labels() {
+        let cache = InMemoryCache::new(1024, 16);
+        let text = OpenMetricsExporter::new("").render(&cache);
+        assert!(text.contains("\nreads_total 0\n"));
+        assert!(text.contains("\nhit_ratio 0\n"));
+        assert!(text.contains("\nevictions_total 0\n"));
+    }
+}
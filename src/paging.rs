@@ -5,13 +5,61 @@
 use std::fmt::Debug;
 use std::future::Future;
 use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use object_store::path::Path;
 use object_store::ObjectMeta;
 
-use crate::Result;
+use crate::{
+    stats::{AtomicIntCacheStats, CacheStats},
+    Error, Result,
+};
+
+/// Identifies one version of an object, from the `e_tag` and `version` of
+/// its [ObjectMeta].
+///
+/// Caches bind the pages of a location to the version returned by
+/// [PageCache::head], so that pages of an older version are never served
+/// once newer metadata has been observed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ObjectVersion {
+    pub(crate) e_tag: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
+impl ObjectVersion {
+    /// Returns `None` if the store reports neither an `e_tag` nor a `version`,
+    /// in which case changes to the object can not be detected.
+    pub(crate) fn of(meta: &ObjectMeta) -> Option<Self> {
+        if meta.e_tag.is_none() && meta.version.is_none() {
+            return None;
+        }
+        Some(Self {
+            e_tag: meta.e_tag.clone(),
+            version: meta.version.clone(),
+        })
+    }
+}
+
+/// Memory used by `meta` in a metadata cache, including its key.
+pub(crate) fn metadata_weight(meta: &ObjectMeta) -> u32 {
+    let size = std::mem::size_of::<u64>()
+        + std::mem::size_of::<ObjectMeta>()
+        + meta.location.as_ref().len()
+        + meta.e_tag.as_ref().map_or(0, String::len)
+        + meta.version.as_ref().map_or(0, String::len);
+    size.try_into().unwrap_or(u32::MAX)
+}
+
+/// Callback invoked with the location, page ID and data of a page that was
+/// evicted from a [PageCache], either to make room for other pages or because
+/// it expired.
+///
+/// Pages that are explicitly invalidated are not reported.
+pub type EvictionListener = Arc<dyn Fn(&Path, u32, Bytes) + Send + Sync>;
 
 /// [PageCache] trait.
 ///
@@ -27,6 +75,16 @@ pub trait PageCache: Sync + Send + Debug + 'static {
     /// Total used cache size in bytes.
     fn size(&self) -> usize;
 
+    /// Stats of the cache.
+    ///
+    /// The cache keeps the [capacity stats](crate::stats::CacheCapacityStats)
+    /// up to date as pages are inserted, evicted, expired or invalidated.
+    ///
+    /// Not tracked by default, the returned stats stay empty.
+    fn stats(&self) -> Arc<dyn CacheStats> {
+        Arc::new(AtomicIntCacheStats::new())
+    }
+
     /// Read data of a page.
     ///
     /// # Parameters
@@ -44,6 +102,25 @@ pub trait PageCache: Sync + Send + Debug + 'static {
         loader: impl Future<Output = Result<Bytes>> + Send,
     ) -> Result<Bytes>;
 
+    /// Read data of a page, like [Self::get_with()], with a `loader` that also
+    /// returns how long loading the page took, such as the latency of the
+    /// inner read that fetched it.
+    ///
+    /// Caches that weigh what it costs to load a page again use this latency,
+    /// instead of the time spent awaiting `loader`, which is close to zero for
+    /// pages fetched along with others. It is ignored by default.
+    async fn get_with_latency(
+        &self,
+        location: &Path,
+        page_id: u32,
+        loader: impl Future<Output = Result<(Bytes, Duration)>> + Send,
+    ) -> Result<Bytes> {
+        self.get_with(location, page_id, async {
+            loader.await.map(|(data, _)| data)
+        })
+        .await
+    }
+
     /// Read cached page.
     ///
     /// # Parameters
@@ -56,6 +133,15 @@ pub trait PageCache: Sync + Send + Debug + 'static {
     /// - `Err(Error)` if an error occurred.
     async fn get(&self, location: &Path, page_id: u32) -> Result<Option<Bytes>>;
 
+    /// Whether the page is cached, without reading it.
+    ///
+    /// The page may still be evicted right after this returns `true`.
+    ///
+    /// Reads the page by default.
+    async fn contains(&self, location: &Path, page_id: u32) -> bool {
+        matches!(self.get(location, page_id).await, Ok(Some(_)))
+    }
+
     /// Get range of data in the page.
     ///
     /// # Parameters
@@ -80,6 +166,10 @@ pub trait PageCache: Sync + Send + Debug + 'static {
     ) -> Result<Option<Bytes>>;
 
     /// Get metadata of the object.
+    ///
+    /// The pages of the location are bound to the `e_tag` / `version` of the
+    /// returned metadata. If it differs from the version the cached pages were
+    /// read for, those pages are dropped.
     async fn head(
         &self,
         location: &Path,
@@ -90,4 +180,21 @@ pub trait PageCache: Sync + Send + Debug + 'static {
     async fn put(&self, location: &Path, page_id: u32, data: Bytes) -> Result<()>;
     /// Remove all pages belong to the location.
     async fn invalidate(&self, location: &Path) -> Result<()>;
+
+    /// Keep `pages` of `location` in the cache once loaded, until they are
+    /// unpinned.
+    ///
+    /// Pinned pages are neither evicted nor expired. They are still dropped
+    /// when the location is invalidated or its object changes, and pinned
+    /// again once loaded again.
+    ///
+    /// Not supported by default.
+    async fn pin(&self, _location: &Path, _pages: Range<u32>) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Unpin `pages` of `location`. They stay cached, as any other page.
+    async fn unpin(&self, _location: &Path, _pages: Range<u32>) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
 }
@@ -1,19 +1,49 @@
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
 use std::ops::Range;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use futures::{stream, stream::BoxStream, StreamExt, TryStreamExt};
+use log::warn;
 use object_store::{
-    path::Path, Attributes, GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload,
-    ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult,
+    path::Path, Attributes, GetOptions, GetRange, GetResult, GetResultPayload, ListResult,
+    MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult,
 };
 
-use crate::{paging::PageCache, stats::CacheStats, Result};
+mod admission;
+mod coalesce;
+mod listing;
+mod multipart;
+mod prefetch;
+mod read_ahead;
+mod streaming;
+
+use admission::{Admission, FrequencySketch};
+pub use admission::{AdmissionFilter, ScanAdmission};
+use coalesce::PageLoader;
+use listing::ListingCache;
+use multipart::CachingMultipartUpload;
+pub use prefetch::PrefetchHandle;
+use prefetch::{prefetch, warm_up};
+pub use read_ahead::ReadAhead;
+use read_ahead::ReadAheadTracker;
+use streaming::page_stream;
+
+use crate::{
+    paging::{ObjectVersion, PageCache},
+    stats::{CacheStats, ReadOutcome},
+    Error, Result,
+};
 
 /// Read-through Page Cache.
 ///
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ReadThroughCache<C: PageCache> {
     inner: Arc<dyn ObjectStore>,
     cache: Arc<C>,
@@ -21,6 +51,47 @@ pub struct ReadThroughCache<C: PageCache> {
     parallelism: usize,
 
     stats: Arc<dyn CacheStats>,
+
+    read_ahead: Option<Arc<ReadAheadTracker>>,
+
+    /// Maximum size of one inner read of consecutive missing pages.
+    coalesce_limit: Option<usize>,
+
+    /// Whether written objects are inserted into the cache.
+    write_through: bool,
+
+    /// Bytes of each multipart upload kept in memory for write-through.
+    multipart_buffer_limit: Option<usize>,
+
+    listings: Option<Arc<ListingCache>>,
+
+    scan_admission: Option<ScanAdmission>,
+
+    /// Whether reads are scans, never admitted.
+    scan: bool,
+
+    /// Miss counts of the admission filter.
+    filter: Option<Arc<FrequencySketch>>,
+}
+
+// Not derived, so that the cache itself does not need to be `Clone`.
+impl<C: PageCache> Clone for ReadThroughCache<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            parallelism: self.parallelism,
+            stats: self.stats.clone(),
+            read_ahead: self.read_ahead.clone(),
+            coalesce_limit: self.coalesce_limit,
+            write_through: self.write_through,
+            multipart_buffer_limit: self.multipart_buffer_limit,
+            listings: self.listings.clone(),
+            scan_admission: self.scan_admission.clone(),
+            scan: self.scan,
+            filter: self.filter.clone(),
+        }
+    }
 }
 
 impl<C: PageCache> std::fmt::Display for ReadThroughCache<C> {
@@ -34,14 +105,16 @@ impl<C: PageCache> std::fmt::Display for ReadThroughCache<C> {
 }
 
 impl<C: PageCache> ReadThroughCache<C> {
+    /// Create a [ReadThroughCache] recording its reads into the stats of `cache`.
     pub fn new(inner: Arc<dyn ObjectStore>, cache: Arc<C>) -> Self {
-        Self::new_with_stats(
-            inner,
-            cache,
-            Arc::new(crate::stats::AtomicIntCacheStats::new()),
-        )
+        let stats = cache.stats();
+        Self::new_with_stats(inner, cache, stats)
     }
 
+    /// Create a [ReadThroughCache] recording its reads into `stats`.
+    ///
+    /// Capacity and usage are recorded into the stats of `cache`, so pass the
+    /// same stats to the cache builder to have them in one place.
     pub fn new_with_stats(
         inner: Arc<dyn ObjectStore>,
         cache: Arc<C>,
@@ -52,63 +125,619 @@ impl<C: PageCache> ReadThroughCache<C> {
             cache,
             parallelism: num_cpus::get(),
             stats,
+            read_ahead: None,
+            coalesce_limit: None,
+            write_through: false,
+            multipart_buffer_limit: None,
+            listings: None,
+            scan_admission: None,
+            scan: false,
+            filter: None,
         }
     }
 
+    /// Cache the results of `list` and `list_with_delimiter` for up to
+    /// `time_to_live`.
+    ///
+    /// Writes through this [ReadThroughCache] drop the cached listings of
+    /// their ancestor prefixes. Writes by other clients are only visible
+    /// once the cached listings expire.
+    ///
+    /// Listings are collected before they are returned, and up to 64 MiB of
+    /// them are kept.
+    pub fn with_list_cache(mut self, time_to_live: Duration) -> Self {
+        self.listings = Some(Arc::new(ListingCache::new(time_to_live)));
+        self
+    }
+
+    /// Insert the objects written with `put` into the cache, instead of only
+    /// invalidating them.
+    ///
+    /// Multipart uploads are only inserted with a
+    /// [buffer limit](Self::with_multipart_buffer_limit).
+    ///
+    /// The metadata is built from the [`PutResult`], with the time of the put
+    /// as `last_modified`, so reading the object back does not reach the
+    /// inner store. Conditional reads are still answered with the metadata of
+    /// the inner store.
+    pub fn with_write_through(mut self, enabled: bool) -> Self {
+        self.write_through = enabled;
+        self
+    }
+
+    /// Keep up to `max_bytes` of each multipart upload in memory until it
+    /// completes, so that [write-through](Self::with_write_through) also
+    /// inserts the objects written with multipart uploads.
+    ///
+    /// Uploads larger than `max_bytes` are not cached. Multipart uploads are
+    /// not buffered by default.
+    pub fn with_multipart_buffer_limit(mut self, max_bytes: usize) -> Self {
+        self.multipart_buffer_limit = Some(max_bytes);
+        self
+    }
+
+    /// Limit the size of the inner reads loading consecutive missing pages.
+    ///
+    /// Pages missing from the cache are loaded with one inner read per run
+    /// of consecutive pages. By default, runs are not limited in size.
+    pub fn with_coalesce_limit(mut self, max_bytes: usize) -> Self {
+        self.coalesce_limit = Some(max_bytes);
+        self
+    }
+
+    /// Prefetch the next pages of paths read sequentially with `get_range`.
+    ///
+    /// Pages are loaded in the background, so this must be used within a
+    /// tokio runtime.
+    pub fn with_read_ahead(mut self, options: ReadAhead) -> Self {
+        self.read_ahead = Some(Arc::new(ReadAheadTracker::new(
+            options,
+            self.cache.page_size(),
+        )));
+        self
+    }
+
+    /// Only insert the pages loaded by the reads admitted by `admission`.
+    ///
+    /// Reads that are not admitted do not trigger read-ahead either.
+    pub fn with_scan_admission(mut self, admission: ScanAdmission) -> Self {
+        self.scan_admission = Some(admission);
+        self
+    }
+
+    /// Only insert the missing pages that pass `filter`, and serve the
+    /// others without inserting them.
+    ///
+    /// The pages admitted and rejected are counted in the stats. Prefetches
+    /// and writes are not filtered.
+    pub fn with_admission_filter(mut self, filter: AdmissionFilter) -> Self {
+        self.filter = Some(Arc::new(FrequencySketch::new(filter)));
+        self
+    }
+
+    /// A view of this cache for a scan: its reads are served from the cache
+    /// and the inner store, but the pages they load are not inserted.
+    ///
+    /// Writes and prefetches are not affected.
+    pub fn for_scan(&self) -> Self {
+        Self {
+            scan: true,
+            ..self.clone()
+        }
+    }
+
+    /// Which of the pages loaded to read `range_len` bytes of an object of
+    /// `object_size` bytes are inserted into the cache.
+    fn admission(&self, object_size: usize, range_len: usize) -> Admission {
+        let scan = self.scan
+            || self
+                .scan_admission
+                .as_ref()
+                .is_some_and(|admission| !admission.admits(object_size, range_len));
+        match &self.filter {
+            _ if scan => Admission::None,
+            Some(filter) => Admission::Filtered(filter.clone()),
+            None => Admission::All,
+        }
+    }
+
+    /// Stats of the reads through this cache.
+    pub fn stats(&self) -> Arc<dyn CacheStats> {
+        self.stats.clone()
+    }
+
     async fn invalidate(&self, location: &Path) -> Result<()> {
         self.cache.invalidate(location).await
     }
+
+    /// Drop the cached listings that may include `location`.
+    async fn invalidate_listings(&self, location: &Path) {
+        if let Some(listings) = &self.listings {
+            listings.invalidate(location).await;
+        }
+    }
+
+    /// Track the read of `range`, and prefetch the following pages if the
+    /// path is read sequentially.
+    async fn read_ahead(&self, location: &Path, range: &Range<usize>) {
+        let Some(tracker) = &self.read_ahead else {
+            return;
+        };
+        let pages = tracker.on_read(location, range).await;
+        if pages.is_empty() {
+            return;
+        }
+        let future = prefetch(
+            self.inner.clone(),
+            self.cache.clone(),
+            self.stats.clone(),
+            location.clone(),
+            pages,
+            self.parallelism,
+            self.coalesce_limit,
+        );
+        let location = location.clone();
+        tokio::spawn(async move {
+            if let Err(e) = future.await {
+                warn!("failed to read ahead {location}: {e}");
+            }
+        });
+    }
+
+    /// Read `range` of `location`, reloading the metadata and retrying once
+    /// if the object changed since its metadata was cached.
+    async fn read_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        match self.try_read_range(location, range.clone()).await {
+            Err(Error::Precondition { .. }) => {
+                self.invalidate(location).await?;
+                self.try_read_range(location, range).await
+            }
+            result => result,
+        }
+    }
+
+    async fn try_read_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let started = Instant::now();
+        let (store, cache, stats) = (&self.inner, &self.cache, &self.stats);
+        let page_size = cache.page_size();
+        let start = (range.start / page_size) * page_size;
+        let meta = cache.head(location, store.head(location)).await?;
+        check_bounds(location, &range, meta.size)?;
+        let admission = self.admission(meta.size, range.len());
+        if !matches!(admission, Admission::None) {
+            self.read_ahead(location, &range).await;
+        }
+        let misses = AtomicUsize::new(0);
+        let loader = PageLoader::new(
+            store.as_ref(),
+            cache.as_ref(),
+            stats.as_ref(),
+            &meta,
+            range.start / page_size..range.end.div_ceil(page_size),
+            self.coalesce_limit,
+        )
+        .await;
+
+        let pages = stream::iter((start..range.end).step_by(page_size))
+            .map(|offset| {
+                let page_id = offset / page_size;
+                let intersection = std::cmp::max(offset, range.start)
+                    ..std::cmp::min(offset + page_size, range.end);
+                let range_in_page = intersection.start - offset..intersection.end - offset;
+                let (loader, misses, admission) = (&loader, &misses, &admission);
+
+                stats.inc_total_reads();
+
+                async move {
+                    let load = async {
+                        misses.fetch_add(1, Ordering::Relaxed);
+                        stats.inc_total_misses();
+                        loader.load(page_id).await
+                    };
+                    let page = read_page(
+                        cache.as_ref(),
+                        stats.as_ref(),
+                        admission,
+                        location,
+                        page_id,
+                        load,
+                    )
+                    .await?;
+                    Ok::<_, Error>(page.slice(range_in_page))
+                }
+            })
+            .buffered(self.parallelism)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let outcome = ReadOutcome::of(pages.len(), misses.into_inner());
+        stats.record_read_latency(outcome, started.elapsed());
+
+        if pages.len() == 1 {
+            return Ok(pages.into_iter().next().unwrap());
+        }
+
+        // stick all bytes together.
+        let mut buf = BytesMut::with_capacity(range.len());
+        for page in pages {
+            buf.extend_from_slice(&page);
+        }
+        Ok(buf.into())
+    }
+
+    /// Read `ranges` of `location`, reloading the metadata and retrying once
+    /// if the object changed since its metadata was cached.
+    async fn read_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        match self.try_read_ranges(location, ranges).await {
+            Err(Error::Precondition { .. }) => {
+                self.invalidate(location).await?;
+                self.try_read_ranges(location, ranges).await
+            }
+            result => result,
+        }
+    }
+
+    async fn try_read_ranges(
+        &self,
+        location: &Path,
+        ranges: &[Range<usize>],
+    ) -> Result<Vec<Bytes>> {
+        let started = Instant::now();
+        let (store, cache, stats) = (&self.inner, &self.cache, &self.stats);
+        let page_size = cache.page_size();
+        let meta = cache.head(location, store.head(location)).await?;
+        for range in ranges {
+            check_bounds(location, range, meta.size)?;
+        }
+        let longest = ranges.iter().map(|r| r.len()).max().unwrap_or(0);
+        let admission = self.admission(meta.size, longest);
+
+        // The union of pages covered by all ranges, each page is read only once.
+        let page_ids = ranges
+            .iter()
+            .filter(|r| !r.is_empty())
+            .flat_map(|r| r.start / page_size..r.end.div_ceil(page_size))
+            .collect::<BTreeSet<_>>();
+        let misses = AtomicUsize::new(0);
+        let loader = PageLoader::new(
+            store.as_ref(),
+            cache.as_ref(),
+            stats.as_ref(),
+            &meta,
+            page_ids.iter().copied(),
+            self.coalesce_limit,
+        )
+        .await;
+
+        let pages = stream::iter(page_ids)
+            .map(|page_id| {
+                let (loader, misses, admission) = (&loader, &misses, &admission);
+
+                stats.inc_total_reads();
+
+                async move {
+                    let load = async {
+                        misses.fetch_add(1, Ordering::Relaxed);
+                        stats.inc_total_misses();
+                        loader.load(page_id).await
+                    };
+                    let page = read_page(
+                        cache.as_ref(),
+                        stats.as_ref(),
+                        admission,
+                        location,
+                        page_id,
+                        load,
+                    )
+                    .await?;
+                    Ok::<_, Error>((page_id, page))
+                }
+            })
+            .buffered(self.parallelism)
+            .try_collect::<HashMap<_, _>>()
+            .await?;
+        let outcome = ReadOutcome::of(pages.len(), misses.into_inner());
+        stats.record_read_latency(outcome, started.elapsed());
+
+        Ok(ranges
+            .iter()
+            .map(|range| {
+                if range.is_empty() {
+                    return Bytes::new();
+                }
+                let first_page = range.start / page_size;
+                let last_page = (range.end - 1) / page_size;
+                if first_page == last_page {
+                    let offset = first_page * page_size;
+                    return pages[&first_page].slice(range.start - offset..range.end - offset);
+                }
+
+                let mut buf = BytesMut::with_capacity(range.len());
+                for page_id in first_page..=last_page {
+                    let offset = page_id * page_size;
+                    let start = std::cmp::max(offset, range.start) - offset;
+                    let end = std::cmp::min(offset + page_size, range.end) - offset;
+                    buf.extend_from_slice(&pages[&page_id][start..end]);
+                }
+                buf.into()
+            })
+            .collect())
+    }
+
+    /// Load the pages covering `ranges` of `location` into the cache, in the
+    /// background.
+    ///
+    /// Ranges past the end of the object are ignored. Dropping the returned
+    /// handle does not stop the prefetch, use [`PrefetchHandle::cancel`].
+    pub fn prefetch(&self, location: &Path, ranges: &[Range<usize>]) -> PrefetchHandle {
+        let page_size = self.cache.page_size();
+        let page_ids = ranges
+            .iter()
+            .filter(|r| !r.is_empty())
+            .flat_map(|r| r.start / page_size..r.end.div_ceil(page_size))
+            .collect::<BTreeSet<_>>();
+        PrefetchHandle::spawn(prefetch(
+            self.inner.clone(),
+            self.cache.clone(),
+            self.stats.clone(),
+            location.clone(),
+            page_ids,
+            self.parallelism,
+            self.coalesce_limit,
+        ))
+    }
+
+    /// Load the objects under `prefix` into the cache, in the background.
+    ///
+    /// Objects are loaded in listing order, from their first page, until
+    /// `max_bytes` are loaded, with at most `concurrency` reads at a time.
+    /// Consecutive missing pages are loaded together, up to the
+    /// [coalesce limit](Self::with_coalesce_limit). Objects that fail to
+    /// load are skipped.
+    pub fn warm_up(
+        &self,
+        prefix: Option<&Path>,
+        max_bytes: usize,
+        concurrency: usize,
+    ) -> PrefetchHandle {
+        PrefetchHandle::spawn(warm_up(
+            self.inner.clone(),
+            self.cache.clone(),
+            self.stats.clone(),
+            prefix.cloned(),
+            max_bytes,
+            concurrency,
+            self.coalesce_limit,
+        ))
+    }
+
+    /// Pin the pages covering `range` of `location`, or all of its pages if
+    /// `None`, and load them into the cache.
+    ///
+    /// Pinned pages are neither evicted nor expired until they are unpinned,
+    /// see [`PageCache::pin`].
+    pub async fn pin(&self, location: &Path, range: Option<Range<usize>>) -> Result<()> {
+        let pages = self.pinned_pages(range);
+        let meta = self.cache.head(location, self.inner.head(location)).await?;
+        self.cache.pin(location, pages.clone()).await?;
+        let num_pages = meta.size.div_ceil(self.cache.page_size());
+        let end = std::cmp::min(pages.end as usize, num_pages);
+        let result = prefetch(
+            self.inner.clone(),
+            self.cache.clone(),
+            self.stats.clone(),
+            location.clone(),
+            pages.start as usize..end,
+            self.parallelism,
+            self.coalesce_limit,
+        )
+        .await;
+        if result.is_err() {
+            self.cache.unpin(location, pages).await?;
+        }
+        result
+    }
+
+    /// Unpin the pages covering `range` of `location`, or all of its pages if
+    /// `None`.
+    pub async fn unpin(&self, location: &Path, range: Option<Range<usize>>) -> Result<()> {
+        self.cache.unpin(location, self.pinned_pages(range)).await
+    }
+
+    fn pinned_pages(&self, range: Option<Range<usize>>) -> Range<u32> {
+        let page_size = self.cache.page_size();
+        match range {
+            Some(range) => (range.start / page_size) as u32..range.end.div_ceil(page_size) as u32,
+            // Including the pages past the end, in case the object grows.
+            None => 0..u32::MAX,
+        }
+    }
 }
 
-async fn get_range<C: PageCache>(
-    store: Arc<dyn ObjectStore>,
-    cache: Arc<C>,
-    stats: Arc<dyn CacheStats>,
+/// Insert the object just written to `location`, made of `chunks`, into the cache.
+async fn write_through<C: PageCache>(
+    cache: &C,
     location: &Path,
-    range: Range<usize>,
-    parallelism: usize,
-) -> Result<Bytes> {
-    let page_size = cache.page_size();
-    let start = (range.start / page_size) * page_size;
-    let meta = cache.head(location, store.head(location)).await?;
-
-    let pages = stream::iter((start..range.end).step_by(page_size))
-        .map(|offset| {
-            let page_cache = cache.clone();
-            let page_id = offset / page_size;
-            let intersection =
-                std::cmp::max(offset, range.start)..std::cmp::min(offset + page_size, range.end);
-            let range_in_page = intersection.start - offset..intersection.end - offset;
-            let page_end = std::cmp::min(offset + page_size, meta.size);
-            let store = store.clone();
-            let stats = stats.clone();
-
-            stats.inc_total_reads();
+    chunks: Vec<Bytes>,
+    result: &PutResult,
+) -> Result<()> {
+    // Reads racing with the write may have cached the previous version.
+    cache.invalidate(location).await?;
+    let meta = ObjectMeta {
+        location: location.clone(),
+        // Not reported by the put, conditional reads revalidate it against
+        // the inner store.
+        last_modified: SystemTime::now().into(),
+        size: chunks.iter().map(Bytes::len).sum(),
+        e_tag: result.e_tag.clone(),
+        version: result.version.clone(),
+    };
+    cache.head(location, async { Ok(meta) }).await?;
 
-            async move {
-                // Actual range in the file.
-                page_cache
-                    .get_range_with(location, page_id as u32, range_in_page, async {
-                        stats.inc_total_misses();
-                        store.get_range(location, offset..page_end).await
-                    })
-                    .await
+    let page_size = cache.page_size();
+    let mut page_id = 0;
+    let mut buf = BytesMut::new();
+    for mut chunk in chunks {
+        while !chunk.is_empty() {
+            // Pages within a chunk are not copied.
+            if buf.is_empty() && chunk.len() >= page_size {
+                cache
+                    .put(location, page_id, chunk.split_to(page_size))
+                    .await?;
+                page_id += 1;
+                continue;
             }
-        })
-        .buffered(parallelism)
-        .try_collect::<Vec<_>>()
-        .await?;
+            let n = std::cmp::min(page_size - buf.len(), chunk.len());
+            buf.extend_from_slice(&chunk.split_to(n));
+            if buf.len() == page_size {
+                cache.put(location, page_id, buf.split().freeze()).await?;
+                page_id += 1;
+            }
+        }
+    }
+    if !buf.is_empty() {
+        cache.put(location, page_id, buf.freeze()).await?;
+    }
+    Ok(())
+}
 
-    if pages.len() == 1 {
-        return Ok(pages.into_iter().next().unwrap());
+/// Check the conditional request headers in `options` against `meta`.
+///
+/// Mirrors the checks done by the `object_store` implementations, so that
+/// callers observe the same [`Error::Precondition`] and [`Error::NotModified`]
+/// errors as they would from the inner store.
+fn check_preconditions(options: &GetOptions, meta: &ObjectMeta) -> Result<()> {
+    // The use of the invalid etag "*" means no ETag is equivalent to never matching
+    let etag = meta.e_tag.as_deref().unwrap_or("*");
+    let last_modified = meta.last_modified;
+
+    if let Some(m) = &options.if_match {
+        if m != "*" && m.split(',').map(str::trim).all(|x| x != etag) {
+            return Err(Error::Precondition {
+                path: meta.location.to_string(),
+                source: format!("{etag} does not match {m}").into(),
+            });
+        }
+    } else if let Some(date) = options.if_unmodified_since {
+        if last_modified > date {
+            return Err(Error::Precondition {
+                path: meta.location.to_string(),
+                source: format!("{date} < {last_modified}").into(),
+            });
+        }
     }
 
-    // stick all bytes together.
-    let mut buf = BytesMut::with_capacity(range.len());
-    for page in pages {
-        buf.extend_from_slice(&page);
+    if let Some(m) = &options.if_none_match {
+        if m == "*" || m.split(',').map(str::trim).any(|x| x == etag) {
+            return Err(Error::NotModified {
+                path: meta.location.to_string(),
+                source: format!("{etag} matches {m}").into(),
+            });
+        }
+    } else if let Some(date) = options.if_modified_since {
+        if last_modified <= date {
+            return Err(Error::NotModified {
+                path: meta.location.to_string(),
+                source: format!("{date} >= {last_modified}").into(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check that `range` is within an object of `size` bytes.
+fn check_bounds(location: &Path, range: &Range<usize>, size: usize) -> Result<()> {
+    if range.end <= size {
+        return Ok(());
+    }
+    Err(Error::Generic {
+        store: "ReadThroughCache",
+        source: format!(
+            "range {}..{} is out of bounds for {location} of {size} bytes",
+            range.start, range.end
+        )
+        .into(),
+    })
+}
+
+/// Whether `options` carry any condition on the version of the object.
+fn has_preconditions(options: &GetOptions) -> bool {
+    options.if_match.is_some()
+        || options.if_none_match.is_some()
+        || options.if_modified_since.is_some()
+        || options.if_unmodified_since.is_some()
+}
+
+/// Resolve a [`GetRange`] against an object of `size` bytes.
+fn resolve_range(location: &Path, range: &GetRange, size: usize) -> Result<Range<usize>> {
+    let invalid = |msg: String| Error::Generic {
+        store: "ReadThroughCache",
+        source: format!("invalid range for {location}: {msg}").into(),
+    };
+    match range {
+        GetRange::Bounded(r) if r.end <= r.start => Err(invalid(format!(
+            "Range started at {} and ended at {}",
+            r.start, r.end
+        ))),
+        GetRange::Bounded(Range { start, .. }) | GetRange::Offset(start) if *start >= size => {
+            Err(invalid(format!(
+                "Wanted range starting at {start}, but object was only {size} bytes long"
+            )))
+        }
+        GetRange::Bounded(r) => Ok(r.start..std::cmp::min(r.end, size)),
+        GetRange::Offset(start) => Ok(*start..size),
+        GetRange::Suffix(n) => Ok(size.saturating_sub(*n)..size),
+    }
+}
+
+/// Read page `page_id` of `location`, loading it with `load` if it is missing.
+///
+/// The loaded page is only inserted into the cache if `admission` admits it.
+async fn read_page<C: PageCache>(
+    cache: &C,
+    stats: &dyn CacheStats,
+    admission: &Admission,
+    location: &Path,
+    page_id: usize,
+    load: impl Future<Output = Result<(Bytes, Duration)>> + Send,
+) -> Result<Bytes> {
+    if let Admission::All = admission {
+        return cache.get_with_latency(location, page_id as u32, load).await;
+    }
+    if let Some(page) = cache.get(location, page_id as u32).await? {
+        return Ok(page);
+    }
+    if admission.admits(stats, location, page_id) {
+        cache.get_with_latency(location, page_id as u32, load).await
+    } else {
+        load.await.map(|(data, _)| data)
+    }
+}
+
+/// Read `range` of the object version described by `meta` from the inner store.
+///
+/// Fails with [`Error::Precondition`] if the object changed since `meta` was
+/// fetched, so that a page of a newer version is never cached for older metadata.
+async fn fetch_range(
+    store: &dyn ObjectStore,
+    location: &Path,
+    meta: &ObjectMeta,
+    range: Range<usize>,
+) -> Result<Bytes> {
+    store
+        .get_opts(location, pinned_options(meta, range))
+        .await?
+        .bytes()
+        .await
+}
+
+/// Options to read `range` of the object version described by `meta`.
+fn pinned_options(meta: &ObjectMeta, range: Range<usize>) -> GetOptions {
+    GetOptions {
+        if_match: meta.e_tag.clone(),
+        version: meta.version.clone(),
+        range: Some(range.into()),
+        ..Default::default()
     }
-    Ok(buf.into())
 }
 
 #[async_trait]
@@ -121,7 +750,23 @@ impl<C: PageCache> ObjectStore for ReadThroughCache<C> {
     ) -> Result<PutResult> {
         self.cache.invalidate(location).await?;
 
-        self.inner.put_opts(location, payload, options).await
+        let data = self.write_through.then(|| payload.clone());
+        let result = self.inner.put_opts(location, payload, options).await;
+        self.invalidate_listings(location).await;
+        let result = result?;
+        match data {
+            Some(data) => {
+                let chunks = data.into_iter().collect();
+                if let Err(e) = write_through(self.cache.as_ref(), location, chunks, &result).await
+                {
+                    warn!("failed to write {location} through the cache: {e}");
+                }
+            }
+            // Reads racing with the put may have cached the previous version,
+            // or recorded the path as missing.
+            None => self.invalidate(location).await?,
+        }
+        Ok(result)
     }
 
     async fn put_multipart_opts(
@@ -131,66 +776,87 @@ impl<C: PageCache> ObjectStore for ReadThroughCache<C> {
     ) -> Result<Box<dyn MultipartUpload>> {
         self.invalidate(location).await?;
 
-        self.inner.put_multipart_opts(location, _opts).await
+        let upload = self.inner.put_multipart_opts(location, _opts).await?;
+        let buffer_limit = self.multipart_buffer_limit.filter(|_| self.write_through);
+        Ok(Box::new(CachingMultipartUpload::new(
+            upload,
+            self.cache.clone(),
+            location.clone(),
+            buffer_limit,
+            self.listings.clone(),
+        )))
     }
 
-    async fn get_opts(&self, _location: &Path, _options: GetOptions) -> Result<GetResult> {
-        todo!()
-    }
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let mut meta = self.head(location).await?;
 
-    async fn get(&self, location: &Path) -> Result<GetResult> {
-        let meta = self.head(location).await?;
-        let file_size = meta.size;
-        let page_size = self.cache.page_size();
-        let inner = self.inner.clone();
-        let cache = self.cache.clone();
-        let stats = self.stats.clone();
-        let location = location.clone();
-        let parallelism = self.parallelism;
+        // The cached metadata may be stale, and conditional requests must be
+        // answered for the current version of the object.
+        if has_preconditions(&options) {
+            let current = self.inner.head(location).await?;
+            let changed = match ObjectVersion::of(&current) {
+                Some(version) => {
+                    Some(version) != ObjectVersion::of(&meta) || current.size != meta.size
+                }
+                // Without a version, only the full metadata tells them apart.
+                None => current != meta,
+            };
+            meta = if changed {
+                self.invalidate(location).await?;
+                self.cache
+                    .head(location, std::future::ready(Ok(current)))
+                    .await?
+            } else {
+                // Same version, the cached pages are still valid, but the
+                // cached `last_modified` may have been seeded by a write.
+                current
+            };
+        }
 
-        // TODO: This might yield too many small reads.
-        let s = stream::iter((0..file_size).step_by(page_size))
-            .map(move |offset| {
-                let loc = location.clone();
-                let store = inner.clone();
-                let stats = stats.clone();
-                let c = cache.clone();
-                let page_size = cache.page_size();
+        // The page cache only holds the latest version of an object, older
+        // versions are served by the inner store directly.
+        if options.version.is_some() && options.version != meta.version {
+            return self.inner.get_opts(location, options).await;
+        }
 
-                async move {
-                    get_range(
-                        store,
-                        c,
-                        stats,
-                        &loc,
-                        offset..offset + page_size,
-                        parallelism,
-                    )
-                    .await
-                }
-            })
-            .buffered(self.parallelism)
-            .boxed();
+        check_preconditions(&options, &meta)?;
+
+        let range = match &options.range {
+            Some(r) => resolve_range(location, r, meta.size)?,
+            None => 0..meta.size,
+        };
+
+        let payload = if options.head {
+            stream::empty().boxed()
+        } else {
+            page_stream(
+                self.inner.clone(),
+                self.cache.clone(),
+                self.stats.clone(),
+                meta.clone(),
+                range.clone(),
+                self.admission(meta.size, range.len()),
+            )
+        };
 
-        let payload = GetResultPayload::Stream(s);
         Ok(GetResult {
-            payload,
-            meta: meta.clone(),
-            range: 0..meta.size,
+            payload: GetResultPayload::Stream(payload),
+            meta,
+            range,
             attributes: Attributes::default(),
         })
     }
 
+    async fn get(&self, location: &Path) -> Result<GetResult> {
+        self.get_opts(location, GetOptions::default()).await
+    }
+
     async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
-        get_range(
-            self.inner.clone(),
-            self.cache.clone(),
-            self.stats.clone(),
-            location,
-            range,
-            self.parallelism,
-        )
-        .await
+        self.read_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.read_ranges(location, ranges).await
     }
 
     async fn head(&self, location: &Path) -> Result<ObjectMeta> {
@@ -199,25 +865,58 @@ impl<C: PageCache> ObjectStore for ReadThroughCache<C> {
 
     async fn delete(&self, location: &Path) -> Result<()> {
         self.invalidate(location).await?;
-        self.inner.delete(location).await
+        let result = self.inner.delete(location).await;
+        self.invalidate_listings(location).await;
+        result
     }
 
     fn list(&'_ self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
-        self.inner.list(prefix)
+        let Some(listings) = &self.listings else {
+            return self.inner.list(prefix);
+        };
+        let prefix = prefix.cloned();
+        stream::once(async move {
+            let key = prefix.clone().unwrap_or_default();
+            let loader = self.inner.list(prefix.as_ref()).try_collect();
+            let objects = listings.list(&key, loader).await?;
+            let stream = stream::iter((0..objects.len()).map(move |i| Ok(objects[i].clone())));
+            Ok::<_, Error>(stream)
+        })
+        .try_flatten()
+        .boxed()
     }
 
     async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
-        self.inner.list_with_delimiter(prefix).await
+        let Some(listings) = &self.listings else {
+            return self.inner.list_with_delimiter(prefix).await;
+        };
+        let key = prefix.cloned().unwrap_or_default();
+        let loader = self.inner.list_with_delimiter(prefix);
+        let result = listings.list_with_delimiter(&key, loader).await?;
+        Ok(ListResult {
+            common_prefixes: result.common_prefixes.clone(),
+            objects: result.objects.clone(),
+        })
     }
 
     async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
         self.invalidate(to).await?;
-        self.inner.copy(from, to).await
+        let result = self.inner.copy(from, to).await;
+        self.invalidate_listings(to).await;
+        result?;
+        // Reads racing with the copy may have cached the previous version,
+        // or recorded the path as missing.
+        self.invalidate(to).await
     }
 
     async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
         self.invalidate(to).await?;
-        self.inner.copy_if_not_exists(from, to).await
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.invalidate_listings(to).await;
+        result?;
+        // Reads racing with the copy may have cached the previous version,
+        // or recorded the path as missing.
+        self.invalidate(to).await
     }
 }
 
@@ -244,4 +943,755 @@ mod tests {
         assert_eq!(data.len(), 9);
         assert_eq!(data, "long text".as_bytes());
     }
+
+    #[tokio::test]
+    async fn test_get_range_past_end_of_file() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::memory::InMemory::new());
+        let path = Path::from("some/file");
+        store.put(&path, "0123456789".into()).await.unwrap();
+        let cache = ReadThroughCache::new(store, cache);
+
+        assert!(matches!(
+            cache.get_range(&path, 5..20).await,
+            Err(Error::Generic { .. })
+        ));
+        assert!(matches!(
+            cache.get_ranges(&path, &[0..2, 8..11]).await,
+            Err(Error::Generic { .. })
+        ));
+        assert_eq!(cache.get_range(&path, 5..10).await.unwrap(), "56789");
+    }
+
+    #[tokio::test]
+    async fn test_read_after_external_overwrite() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::memory::InMemory::new());
+        let path = Path::from("some/file");
+        store.put(&path, "0123456789abcdef".into()).await.unwrap();
+        let cache = ReadThroughCache::new(store.clone(), cache);
+        assert_eq!(cache.get_range(&path, 0..4).await.unwrap(), "0123");
+
+        // Overwritten by another writer, the cached metadata is stale.
+        store.put(&path, "ABCDEFGHIJKLMNOP".into()).await.unwrap();
+        assert_eq!(cache.get_range(&path, 8..12).await.unwrap(), "IJKL");
+        assert_eq!(cache.get_range(&path, 0..4).await.unwrap(), "ABCD");
+
+        store.put(&path, "qrstuvwxyz012345".into()).await.unwrap();
+        assert_eq!(
+            cache.get_ranges(&path, &[4..6, 12..14]).await.unwrap(),
+            vec!["uv", "23"]
+        );
+
+        // A stream fails once the change is noticed, the next read reloads it.
+        store.put(&path, "QRSTUVWXYZ!@#$%^".into()).await.unwrap();
+        assert!(matches!(
+            cache.get(&path).await.unwrap().bytes().await,
+            Err(Error::Precondition { .. })
+        ));
+        let data = cache.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, "QRSTUVWXYZ!@#$%^");
+    }
+
+    #[tokio::test]
+    async fn test_get_opts_ranges() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let data = cache.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, "this is a long text".as_bytes());
+
+        for (range, expected) in [
+            (GetRange::Bounded(5..9), "is a"),
+            (GetRange::Bounded(15..100), "text"),
+            (GetRange::Offset(10), "long text"),
+            (GetRange::Suffix(4), "text"),
+            (GetRange::Suffix(100), "this is a long text"),
+        ] {
+            let options = GetOptions {
+                range: Some(range.clone()),
+                ..Default::default()
+            };
+            let result = cache.get_opts(&path, options).await.unwrap();
+            assert_eq!(result.range.len(), expected.len(), "range {range}");
+            assert_eq!(result.bytes().await.unwrap(), expected.as_bytes());
+        }
+
+        for range in [GetRange::Bounded(5..5), GetRange::Offset(19)] {
+            let options = GetOptions {
+                range: Some(range),
+                ..Default::default()
+            };
+            assert!(matches!(
+                cache.get_opts(&path, options).await,
+                Err(Error::Generic { .. })
+            ));
+        }
+
+        let options = GetOptions {
+            head: true,
+            ..Default::default()
+        };
+        let result = cache.get_opts(&path, options).await.unwrap();
+        assert_eq!(result.meta.size, 19);
+        assert!(result.bytes().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_streams() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let stats = cache.stats();
+
+        // Only the pages polled by the consumer are loaded.
+        let mut pages = cache.get(&path).await.unwrap().into_stream();
+        assert_eq!(pages.next().await.unwrap().unwrap(), "this");
+        drop(pages);
+        assert_eq!(stats.total_misses(), 1);
+        assert_eq!(stats.usage(), 4);
+
+        cache.get_range(&path, 8..12).await.unwrap();
+        assert_eq!(stats.fetch_size().count(), 2);
+
+        // Pages 1 and 3 to 4 are read with one request each, 0 and 2 are cached.
+        let data = cache.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, "this is a long text");
+        assert_eq!(stats.total_misses(), 5);
+        assert_eq!(stats.fetch_size().count(), 4);
+        // Each request stops at the next cached page.
+        assert_eq!(stats.fetch_size().sum, 19 + 4 + 4 + 7);
+        assert_eq!(stats.usage(), 19);
+
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(5..13)),
+            ..Default::default()
+        };
+        let data = cache.get_opts(&path, options).await.unwrap();
+        assert_eq!(data.bytes().await.unwrap(), "is a lon");
+        assert_eq!(stats.total_misses(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_write_through() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            object_store::local::LocalFileSystem::new_with_prefix(tmp_dir.path()).unwrap(),
+        );
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store.clone(), cache).with_write_through(true);
+        let path = Path::from("some/file");
+        let stats = cache.stats();
+
+        let payload = PutPayload::from_iter([Bytes::from("this is "), Bytes::from("a long text")]);
+        let result = cache.put(&path, payload).await.unwrap();
+        assert_eq!(stats.usage(), 19);
+
+        let meta = cache.head(&path).await.unwrap();
+        assert_eq!(meta.size, 19);
+        assert_eq!(meta.e_tag, result.e_tag);
+        assert_eq!(meta.e_tag, store.head(&path).await.unwrap().e_tag);
+        assert_eq!(
+            cache.get_range(&path, 3..17).await.unwrap(),
+            "s is a long te"
+        );
+        assert_eq!(stats.total_misses(), 0);
+        assert_eq!(stats.fetch_size().count(), 0);
+
+        // Conditional reads of the written version keep the cached pages.
+        let options = GetOptions {
+            if_match: result.e_tag.clone(),
+            ..Default::default()
+        };
+        let get = cache.get_opts(&path, options).await.unwrap();
+        assert_eq!(get.meta, store.head(&path).await.unwrap());
+        assert_eq!(get.bytes().await.unwrap(), "this is a long text");
+        assert_eq!(stats.total_misses(), 0);
+
+        // Overwriting replaces the cached pages.
+        cache.put(&path, "short".into()).await.unwrap();
+        assert_eq!(cache.get_range(&path, 0..5).await.unwrap(), "short");
+        assert_eq!(stats.total_misses(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_write_through() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            object_store::local::LocalFileSystem::new_with_prefix(tmp_dir.path()).unwrap(),
+        );
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store, cache).with_write_through(true);
+        let path = Path::from("some/file");
+        let stats = cache.stats();
+
+        // Multipart uploads are not buffered by default.
+        let mut upload = cache.put_multipart(&path).await.unwrap();
+        upload.put_part("not cached".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_eq!(stats.usage(), 0);
+
+        let cache = cache.with_multipart_buffer_limit(32);
+        let mut upload = cache.put_multipart(&path).await.unwrap();
+        upload.put_part("this ".into()).await.unwrap();
+        upload.put_part("is a lo".into()).await.unwrap();
+        upload.put_part("ng text".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_eq!(stats.usage(), 19);
+        assert_eq!(
+            cache.get_range(&path, 0..19).await.unwrap(),
+            "this is a long text"
+        );
+        assert_eq!(stats.total_misses(), 0);
+
+        // Aborted uploads are not cached.
+        let aborted = Path::from("some/aborted");
+        let mut upload = cache.put_multipart(&aborted).await.unwrap();
+        upload.put_part("never written".into()).await.unwrap();
+        upload.abort().await.unwrap();
+        assert_eq!(stats.usage(), 19);
+        assert!(cache.get_range(&aborted, 0..5).await.is_err());
+
+        // Uploads over the limit are not cached.
+        let large = Path::from("some/large");
+        let mut upload = cache.put_multipart(&large).await.unwrap();
+        upload.put_part(vec![1; 24].into()).await.unwrap();
+        upload.put_part(vec![2; 24].into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_eq!(stats.usage(), 19);
+    }
+
+    #[tokio::test]
+    async fn test_list_cache() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            object_store::local::LocalFileSystem::new_with_prefix(tmp_dir.path()).unwrap(),
+        );
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store.clone(), cache)
+            .with_list_cache(std::time::Duration::from_secs(60));
+        let list = |prefix: &'static str| {
+            let cache = &cache;
+            async move {
+                let prefix = Path::from(prefix);
+                let mut names = cache
+                    .list(Some(&prefix))
+                    .map_ok(|meta| meta.location.to_string())
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap();
+                names.sort();
+                names
+            }
+        };
+
+        store.put(&Path::from("a/b/1"), "1".into()).await.unwrap();
+        assert_eq!(list("a").await, vec!["a/b/1"]);
+        assert_eq!(list("a/b").await, vec!["a/b/1"]);
+        let result = cache.list_with_delimiter(None).await.unwrap();
+        assert_eq!(result.common_prefixes, vec![Path::from("a")]);
+
+        // Writes to the inner store are not visible until the listing expires.
+        store.put(&Path::from("a/b/2"), "2".into()).await.unwrap();
+        assert_eq!(list("a").await, vec!["a/b/1"]);
+
+        // Writes through the cache drop the listings of ancestor prefixes.
+        cache.put(&Path::from("a/c/3"), "3".into()).await.unwrap();
+        assert_eq!(list("a").await, vec!["a/b/1", "a/b/2", "a/c/3"]);
+        assert_eq!(list("a/b").await, vec!["a/b/1"]);
+        cache.put(&Path::from("d"), "4".into()).await.unwrap();
+        let result = cache.list_with_delimiter(None).await.unwrap();
+        assert_eq!(result.common_prefixes, vec![Path::from("a")]);
+        assert_eq!(result.objects.len(), 1);
+
+        cache.delete(&Path::from("a/b/1")).await.unwrap();
+        assert_eq!(list("a/b").await, vec!["a/b/2"]);
+        cache
+            .copy(&Path::from("a/b/2"), &Path::from("a/b/5"))
+            .await
+            .unwrap();
+        assert_eq!(list("a/b").await, vec!["a/b/2", "a/b/5"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_opts_preconditions() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 1024));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let meta = cache.head(&path).await.unwrap();
+        let etag = meta.e_tag.clone().unwrap();
+
+        let get = |options: GetOptions| {
+            let cache = &cache;
+            let path = &path;
+            async move { cache.get_opts(path, options).await }
+        };
+
+        assert!(get(GetOptions {
+            if_match: Some(etag.clone()),
+            ..Default::default()
+        })
+        .await
+        .is_ok());
+        assert!(matches!(
+            get(GetOptions {
+                if_match: Some("not-an-etag".to_string()),
+                ..Default::default()
+            })
+            .await,
+            Err(Error::Precondition { .. })
+        ));
+        assert!(matches!(
+            get(GetOptions {
+                if_none_match: Some(etag.clone()),
+                ..Default::default()
+            })
+            .await,
+            Err(Error::NotModified { .. })
+        ));
+        assert!(matches!(
+            get(GetOptions {
+                if_modified_since: Some(meta.last_modified),
+                ..Default::default()
+            })
+            .await,
+            Err(Error::NotModified { .. })
+        ));
+        assert!(matches!(
+            get(GetOptions {
+                if_unmodified_since: Some(meta.last_modified - std::time::Duration::from_secs(1)),
+                ..Default::default()
+            })
+            .await,
+            Err(Error::Precondition { .. })
+        ));
+        assert!(get(GetOptions {
+            if_unmodified_since: Some(meta.last_modified),
+            ..Default::default()
+        })
+        .await
+        .is_ok());
+
+        // Preconditions are checked against the current version, not the
+        // cached metadata.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(temp_file.to_str().unwrap(), "this is a longer text").unwrap();
+        assert!(matches!(
+            get(GetOptions {
+                if_match: Some(etag.clone()),
+                ..Default::default()
+            })
+            .await,
+            Err(Error::Precondition { .. })
+        ));
+        let result = get(GetOptions {
+            if_none_match: Some(etag.clone()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            result.bytes().await.unwrap(),
+            Bytes::from("this is a longer text")
+        );
+        assert_eq!(cache.head(&path).await.unwrap().size, 21);
+    }
+
+    #[tokio::test]
+    async fn test_get_ranges() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let data = cache
+            .get_ranges(&path, &[0..4, 5..9, 2..7, 10..10, 15..19])
+            .await
+            .unwrap();
+        assert_eq!(
+            data,
+            vec![
+                Bytes::from("this"),
+                Bytes::from("is a"),
+                Bytes::from("is is"),
+                Bytes::new(),
+                Bytes::from("text"),
+            ]
+        );
+        // Pages 0, 1, 2, 3 and 4 are each read once.
+        assert_eq!(cache.stats.total_reads(), 5);
+        assert_eq!(cache.stats.total_misses(), 5);
+
+        cache.get_ranges(&path, &[1..3, 8..12]).await.unwrap();
+        assert_eq!(cache.stats.total_reads(), 7);
+        assert_eq!(cache.stats.total_misses(), 5);
+
+        assert!(cache.get_ranges(&path, &[0..4, 10..20]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let stats = cache.stats();
+        assert_eq!(stats.max_capacity(), 1024 * 1024);
+        assert_eq!(stats.usage(), 0);
+
+        cache.get_range(&path, 0..10).await.unwrap();
+        assert_eq!(stats.total_reads(), 3);
+        assert_eq!(stats.total_misses(), 3);
+        assert_eq!(stats.usage(), 12);
+
+        cache.get_range(&path, 2..19).await.unwrap();
+        assert_eq!(stats.total_reads(), 8);
+        assert_eq!(stats.total_misses(), 5);
+        assert_eq!(stats.usage(), 19);
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache).with_read_ahead(ReadAhead {
+            initial_pages: 2,
+            max_pages: 8,
+            ..Default::default()
+        });
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let stats = cache.stats();
+
+        cache.get_range(&path, 0..4).await.unwrap();
+        cache.get_range(&path, 4..8).await.unwrap();
+        for _ in 0..100 {
+            if stats.total_prefetches() >= 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(stats.total_prefetches(), 2);
+        assert_eq!(stats.total_misses(), 2);
+
+        // Pages 2 and 3 were loaded ahead of the read.
+        assert_eq!(cache.get_range(&path, 8..16).await.unwrap(), "a long t");
+        assert_eq!(stats.total_misses(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let stats = cache.stats();
+
+        cache
+            .prefetch(&path, &[0..2, 9..13, 100..200])
+            .wait()
+            .await
+            .unwrap();
+        // Pages 0, 2 and 3.
+        assert_eq!(stats.total_prefetches(), 3);
+        assert_eq!(cache.get_range(&path, 8..16).await.unwrap(), "a long t");
+        assert_eq!(stats.total_misses(), 0);
+
+        let missing = Path::from("not/a/file");
+        assert!(matches!(
+            cache.prefetch(&missing, &[0..2, 4..6]).wait().await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_not_found_cleared_by_write() {
+        let cache = Arc::new(
+            InMemoryCache::builder(1024)
+                .page_size(4)
+                .not_found_ttl(Duration::from_secs(60))
+                .build(),
+        );
+        let store = Arc::new(object_store::memory::InMemory::new());
+        let cache = ReadThroughCache::new(store, cache);
+        let path = Path::from("some/file");
+
+        // Read while the upload is in flight, the path is recorded as missing.
+        let mut upload = cache.put_multipart(&path).await.unwrap();
+        upload.put_part("this is a long text".into()).await.unwrap();
+        assert!(matches!(
+            cache.head(&path).await,
+            Err(Error::NotFound { .. })
+        ));
+        upload.complete().await.unwrap();
+        assert_eq!(cache.get_range(&path, 8..14).await.unwrap(), "a long");
+
+        let copy = Path::from("some/copy");
+        assert!(cache.head(&copy).await.is_err());
+        cache.copy(&path, &copy).await.unwrap();
+        assert_eq!(cache.get_range(&copy, 0..4).await.unwrap(), "this");
+    }
+
+    #[tokio::test]
+    async fn test_pin() {
+        let cache = Arc::new(
+            InMemoryCache::builder(16)
+                .page_size(4)
+                .pinned_capacity(8)
+                .build(),
+        );
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let stats = cache.stats();
+
+        cache.pin(&path, Some(15..19)).await.unwrap();
+        // The last two pages.
+        assert_eq!(stats.total_prefetches(), 2);
+        assert_eq!(stats.pinned_usage(), 7);
+        assert_eq!(stats.usage(), 0);
+
+        // They outlive a scan of the object.
+        let other = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(other.to_str().unwrap(), [0; 64]).unwrap();
+        let other = Path::from(other.to_str().unwrap());
+        cache.get(&other).await.unwrap().bytes().await.unwrap();
+        assert_eq!(cache.get_range(&path, 16..19).await.unwrap(), "ext");
+        assert_eq!(stats.total_misses(), 16);
+
+        cache.unpin(&path, None).await.unwrap();
+        assert_eq!(stats.pinned_usage(), 0);
+
+        // Nothing is pinned for a missing path, even once it is written.
+        let missing = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let missing_path = Path::from(missing.to_str().unwrap());
+        std::fs::remove_file(&missing).unwrap();
+        assert!(matches!(
+            cache.pin(&missing_path, None).await,
+            Err(Error::NotFound { .. })
+        ));
+        std::fs::write(&missing, "now it exists").unwrap();
+        cache
+            .get(&missing_path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(stats.pinned_usage(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(tmp_dir.path().join(name), [0; 10]).unwrap();
+        }
+        let store = Arc::new(
+            object_store::local::LocalFileSystem::new_with_prefix(tmp_dir.path()).unwrap(),
+        );
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store, cache);
+        let stats = cache.stats();
+
+        // The first object and a half are loaded.
+        cache.warm_up(None, 15, 2).wait().await.unwrap();
+        assert_eq!(stats.total_prefetches(), 5);
+        assert_eq!(stats.usage(), 18);
+        // With one read per object.
+        assert_eq!(stats.fetch_size().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_latency_stats() {
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let cache = ReadThroughCache::new(store, cache);
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+        let stats = cache.stats();
+
+        cache.get_range(&path, 0..6).await.unwrap();
+        cache.get_range(&path, 1..3).await.unwrap();
+        cache.get_range(&path, 2..10).await.unwrap();
+        cache.get_ranges(&path, &[0..4, 4..8]).await.unwrap();
+        assert_eq!(stats.read_latency(ReadOutcome::Miss).count(), 1);
+        assert_eq!(stats.read_latency(ReadOutcome::Hit).count(), 2);
+        assert_eq!(stats.read_latency(ReadOutcome::PartialHit).count(), 1);
+
+        // One inner read per run of missed pages.
+        let fetch_size = stats.fetch_size();
+        assert_eq!(fetch_size.count(), 2);
+        assert_eq!(fetch_size.sum, 12);
+        assert_eq!(stats.fetch_latency().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_misses() {
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store.clone(), cache);
+        let stats = cache.stats();
+        cache.get_range(&path, 5..9).await.unwrap();
+        assert_eq!(stats.fetch_size().count(), 1);
+        // Pages 1 and 2 are cached, page 0 and pages 3 to 4 take one read each.
+        assert_eq!(
+            cache.get_range(&path, 0..19).await.unwrap(),
+            "this is a long text"
+        );
+        assert_eq!(stats.total_misses(), 5);
+        assert_eq!(stats.fetch_size().count(), 3);
+        assert_eq!(stats.fetch_size().sum, 19);
+
+        // Runs are split at the limit.
+        let cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store, cache).with_coalesce_limit(8);
+        let stats = cache.stats();
+        assert_eq!(
+            cache.get_ranges(&path, &[0..19, 1..2]).await.unwrap()[0],
+            "this is a long text"
+        );
+        assert_eq!(stats.total_misses(), 5);
+        assert_eq!(stats.fetch_size().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_miss_latency_cost() {
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let load_times = Arc::new(std::sync::Mutex::new(vec![]));
+        let cache = Arc::new(
+            InMemoryCache::builder(1024 * 1024)
+                .page_size(4)
+                .eviction_policy(crate::memory::EvictionPolicy::GreedyDualSize)
+                .cost({
+                    let load_times = load_times.clone();
+                    Arc::new(move |_, _, load_time| {
+                        load_times.lock().unwrap().push(load_time.unwrap());
+                        1.0
+                    })
+                })
+                .build(),
+        );
+        let cache = ReadThroughCache::new(store, cache);
+        let stats = cache.stats();
+
+        // Pages loaded by one inner read all cost its latency.
+        cache.get_range(&path, 0..19).await.unwrap();
+        assert_eq!(stats.fetch_size().count(), 1);
+        let load_times = load_times.lock().unwrap().clone();
+        assert_eq!(load_times.len(), 5);
+        assert!(load_times.iter().all(|&t| t == load_times[0]));
+        assert!(load_times[0] > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_scan_admission() {
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let page_cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache =
+            ReadThroughCache::new(store, page_cache.clone()).with_scan_admission(ScanAdmission {
+                max_range_len: Some(8),
+                ..Default::default()
+            });
+
+        // Long reads are served, but their pages are not kept.
+        assert_eq!(
+            cache.get_range(&path, 0..19).await.unwrap(),
+            "this is a long text"
+        );
+        assert_eq!(
+            cache.get_ranges(&path, &[0..4, 5..19]).await.unwrap(),
+            vec!["this", "is a long text"]
+        );
+        for page_id in 0..5 {
+            assert!(!page_cache.contains(&path, page_id).await);
+        }
+
+        cache.get_range(&path, 5..9).await.unwrap();
+        assert!(page_cache.contains(&path, 1).await);
+        assert!(page_cache.contains(&path, 2).await);
+
+        // Scans are served from the cached pages too.
+        let misses = cache.stats().total_misses();
+        let scan = cache.for_scan();
+        let data = scan.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, "this is a long text");
+        assert_eq!(cache.stats().total_misses(), misses + 3);
+        assert_eq!(scan.get_range(&path, 12..14).await.unwrap(), "ng");
+        assert!(!page_cache.contains(&path, 3).await);
+        assert!(!page_cache.contains(&path, 0).await);
+    }
+
+    #[tokio::test]
+    async fn test_admission_filter() {
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        let temp_file = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(temp_file.to_str().unwrap(), "this is a long text").unwrap();
+        let path = Path::from(temp_file.to_str().unwrap());
+
+        let page_cache = Arc::new(InMemoryCache::new(1024 * 1024, 4));
+        let cache = ReadThroughCache::new(store, page_cache.clone())
+            .with_admission_filter(AdmissionFilter::default());
+        let stats = cache.stats();
+
+        // Pages are only inserted on their second miss.
+        assert_eq!(cache.get_range(&path, 0..6).await.unwrap(), "this i");
+        assert!(!page_cache.contains(&path, 0).await);
+        assert_eq!(stats.total_rejected(), 2);
+
+        let data = cache.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data, "this is a long text");
+        assert!(page_cache.contains(&path, 0).await);
+        assert!(page_cache.contains(&path, 1).await);
+        assert!(!page_cache.contains(&path, 2).await);
+        assert_eq!(stats.total_admitted(), 2);
+        assert_eq!(stats.total_rejected(), 5);
+
+        // Hits do not count.
+        cache.get_ranges(&path, &[0..4, 5..6]).await.unwrap();
+        assert_eq!(stats.total_admitted(), 2);
+        assert_eq!(stats.total_rejected(), 5);
+    }
 }
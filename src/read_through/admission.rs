@@ -0,0 +1,210 @@
+//! Admission of the pages loaded by reads
+//!
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex},
+};
+
+use object_store::path::Path;
+
+use crate::stats::CacheStats;
+
+/// Counters per page expected to be recorded within a window.
+const COUNTERS_PER_PAGE: usize = 2;
+
+/// Counters updated per page.
+const HASHES: u64 = 4;
+
+/// Which reads of a [`ReadThroughCache`](crate::ReadThroughCache) insert the
+/// pages they load into the cache.
+///
+/// Reads that are not admitted, typically large scans, are served from the
+/// cached pages and the inner store, without inserting the missing pages, so
+/// they do not evict the pages of other reads.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use object_store::memory::InMemory;
+/// use ocra::{memory::InMemoryCache, ReadThroughCache, ScanAdmission};
+///
+/// let cache = ReadThroughCache::new(
+///     Arc::new(InMemory::new()),
+///     Arc::new(InMemoryCache::builder(64 * 1024 * 1024).build()),
+/// )
+/// .with_scan_admission(ScanAdmission {
+///     max_range_len: Some(8 * 1024 * 1024),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScanAdmission {
+    /// Reads of objects larger than this are not admitted.
+    pub max_object_size: Option<usize>,
+
+    /// Reads longer than this are not admitted. For `get_ranges`, this is
+    /// compared to the longest range.
+    pub max_range_len: Option<usize>,
+}
+
+impl ScanAdmission {
+    /// Whether a read of `range_len` bytes of an object of `object_size`
+    /// bytes is admitted.
+    pub(super) fn admits(&self, object_size: usize, range_len: usize) -> bool {
+        self.max_object_size.is_none_or(|max| object_size <= max)
+            && self.max_range_len.is_none_or(|max| range_len <= max)
+    }
+}
+
+/// Frequency-based admission filter of a
+/// [`ReadThroughCache`](crate::ReadThroughCache), also known as a doorkeeper.
+///
+/// Pages missing from the cache are only inserted once they missed
+/// `min_misses` times within the last `window` misses, so that pages read
+/// once do not evict others. Pages that are not admitted are still served.
+///
+/// Misses are counted in a count-min sketch, so a page may be admitted early
+/// when it collides with pages that missed often.
+#[derive(Debug, Clone)]
+pub struct AdmissionFilter {
+    /// Misses of a page before it is admitted.
+    pub min_misses: u8,
+
+    /// Misses after which all counts are halved.
+    pub window: usize,
+}
+
+impl Default for AdmissionFilter {
+    fn default() -> Self {
+        Self {
+            min_misses: 2,
+            window: 100_000,
+        }
+    }
+}
+
+/// Approximate miss counts of recent pages.
+#[derive(Debug)]
+pub(super) struct FrequencySketch {
+    min_misses: u8,
+    window: usize,
+    hasher: RandomState,
+    state: Mutex<SketchState>,
+}
+
+#[derive(Debug)]
+struct SketchState {
+    counters: Vec<u8>,
+
+    /// Misses recorded since the counters were last halved.
+    recorded: usize,
+}
+
+impl FrequencySketch {
+    pub(super) fn new(filter: AdmissionFilter) -> Self {
+        let width = (filter.window * COUNTERS_PER_PAGE).next_power_of_two();
+        Self {
+            min_misses: filter.min_misses,
+            window: filter.window,
+            hasher: RandomState::new(),
+            state: Mutex::new(SketchState {
+                counters: vec![0; width],
+                recorded: 0,
+            }),
+        }
+    }
+
+    /// Record a miss of `page_id` of `location`, and return whether it is
+    /// admitted.
+    pub(super) fn record(&self, location: &Path, page_id: usize) -> bool {
+        let hash = self.hasher.hash_one((location, page_id));
+        // Double hashing, see "Less Hashing, Same Performance".
+        let (h1, h2) = (hash, (hash >> 32) | 1);
+
+        let mut state = self.state.lock().unwrap();
+        let mask = state.counters.len() - 1;
+        let indices = (0..HASHES).map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) as usize) & mask);
+        let count = indices
+            .clone()
+            .map(|i| state.counters[i])
+            .min()
+            .unwrap_or(0);
+        // Conservative update: only the smallest counters are increased.
+        let count = count.saturating_add(1);
+        for i in indices {
+            state.counters[i] = std::cmp::max(state.counters[i], count);
+        }
+
+        state.recorded += 1;
+        if state.recorded >= self.window {
+            state.recorded = 0;
+            state.counters.iter_mut().for_each(|c| *c /= 2);
+        }
+        count >= self.min_misses
+    }
+}
+
+/// Whether the pages loaded by a read are inserted into the cache.
+#[derive(Debug, Clone)]
+pub(super) enum Admission {
+    All,
+    None,
+    Filtered(Arc<FrequencySketch>),
+}
+
+impl Admission {
+    /// Whether to insert page `page_id` of `location`, missing from the cache.
+    pub(super) fn admits(&self, stats: &dyn CacheStats, location: &Path, page_id: usize) -> bool {
+        match self {
+            Self::All => true,
+            Self::None => false,
+            Self::Filtered(sketch) => {
+                let admitted = sketch.record(location, page_id);
+                if admitted {
+                    stats.inc_total_admitted();
+                } else {
+                    stats.inc_total_rejected();
+                }
+                admitted
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admits() {
+        assert!(ScanAdmission::default().admits(usize::MAX, usize::MAX));
+
+        let admission = ScanAdmission {
+            max_object_size: Some(100),
+            max_range_len: Some(10),
+        };
+        assert!(admission.admits(100, 10));
+        assert!(!admission.admits(101, 10));
+        assert!(!admission.admits(100, 11));
+    }
+
+    #[test]
+    fn test_frequency_sketch() {
+        let sketch = FrequencySketch::new(AdmissionFilter {
+            min_misses: 3,
+            window: 1000,
+        });
+        let location = Path::from("a");
+        assert!(!sketch.record(&location, 0));
+        assert!(!sketch.record(&location, 0));
+        assert!(sketch.record(&location, 0));
+        assert!(!sketch.record(&Path::from("b"), 0));
+
+        // Counts are halved at the end of each window.
+        for page_id in 1..997 {
+            sketch.record(&location, page_id);
+        }
+        assert!(!sketch.record(&location, 0));
+        assert!(sketch.record(&location, 0));
+    }
+}
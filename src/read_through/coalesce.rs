@@ -0,0 +1,158 @@
+//! Coalescing of consecutive page misses
+//!
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use object_store::{ObjectMeta, ObjectStore};
+
+use super::fetch_range;
+use crate::{paging::PageCache, stats::CacheStats, Error, Result};
+
+type SharedFetch<'a> = Shared<BoxFuture<'a, std::result::Result<(Bytes, Duration), Arc<Error>>>>;
+
+/// A read from the inner store covering consecutive pages.
+struct Run<'a> {
+    /// Offset of the first page of the run in the object.
+    offset: usize,
+
+    /// Only issued once one of the pages is loaded.
+    fetch: SharedFetch<'a>,
+}
+
+/// Loads the pages of one read from the inner store.
+///
+/// Consecutive pages that are not cached when the read starts are loaded
+/// together, with one request per run of at most `max_bytes`. Each run is
+/// only requested once one of its pages is loaded, so pages loaded by a
+/// concurrent reader in the meantime do not cause an extra request.
+pub(super) struct PageLoader<'a> {
+    store: &'a dyn ObjectStore,
+    stats: &'a dyn CacheStats,
+    meta: &'a ObjectMeta,
+    page_size: usize,
+
+    /// Run of each page that was missing when the read started.
+    runs: HashMap<usize, Arc<Run<'a>>>,
+}
+
+impl<'a> PageLoader<'a> {
+    /// Plan the loading of `page_ids`, in ascending order.
+    pub(super) async fn new<C: PageCache>(
+        store: &'a dyn ObjectStore,
+        cache: &C,
+        stats: &'a dyn CacheStats,
+        meta: &'a ObjectMeta,
+        page_ids: impl IntoIterator<Item = usize>,
+        max_bytes: Option<usize>,
+    ) -> Self {
+        let page_size = cache.page_size();
+        let max_pages = max_bytes.map_or(usize::MAX, |max| std::cmp::max(max / page_size, 1));
+
+        let mut runs = vec![];
+        let mut current: Vec<usize> = vec![];
+        for page_id in page_ids {
+            if cache.contains(&meta.location, page_id as u32).await {
+                runs.push(std::mem::take(&mut current));
+                continue;
+            }
+            let adjacent = current.last().is_some_and(|&last| last + 1 == page_id);
+            if !adjacent || current.len() >= max_pages {
+                runs.push(std::mem::take(&mut current));
+            }
+            current.push(page_id);
+        }
+        runs.push(current);
+
+        let mut loader = Self {
+            store,
+            stats,
+            meta,
+            page_size,
+            runs: HashMap::new(),
+        };
+        for pages in runs.into_iter().filter(|pages| pages.len() > 1) {
+            let offset = pages[0] * page_size;
+            let end = std::cmp::min((pages[pages.len() - 1] + 1) * page_size, meta.size);
+            let run = Arc::new(Run {
+                offset,
+                fetch: loader
+                    .fetch(offset..end)
+                    .map(|res| res.map_err(Arc::new))
+                    .boxed()
+                    .shared(),
+            });
+            loader
+                .runs
+                .extend(pages.into_iter().map(|page_id| (page_id, run.clone())));
+        }
+        loader
+    }
+
+    /// Read `range` of the object from the inner store, and return it with
+    /// the latency of the read.
+    fn fetch(&self, range: Range<usize>) -> BoxFuture<'a, Result<(Bytes, Duration)>> {
+        let (store, stats, meta) = (self.store, self.stats, self.meta);
+        async move {
+            let start = Instant::now();
+            let data = fetch_range(store, &meta.location, meta, range).await?;
+            let latency = start.elapsed();
+            stats.record_fetch(latency, data.len());
+            Ok((data, latency))
+        }
+        .boxed()
+    }
+
+    /// Load page `page_id` from the inner store, and return it with the
+    /// latency of the read that loaded it.
+    pub(super) async fn load(&self, page_id: usize) -> Result<(Bytes, Duration)> {
+        let offset = page_id * self.page_size;
+        let page_end = std::cmp::min(offset + self.page_size, self.meta.size);
+        let Some(run) = self.runs.get(&page_id) else {
+            return self.fetch(offset..page_end).await;
+        };
+
+        let (data, latency) = run.fetch.clone().await.map_err(unshare)?;
+        let start = std::cmp::min(offset - run.offset, data.len());
+        let end = std::cmp::min(page_end - run.offset, data.len());
+        // Copy the page out, a slice would keep the whole run alive for as
+        // long as the page is cached.
+        Ok((Bytes::copy_from_slice(&data[start..end]), latency))
+    }
+}
+
+/// Convert the error of a shared fetch back into an [`Error`], keeping the
+/// variants callers match on.
+fn unshare(e: Arc<Error>) -> Error {
+    let e = match Arc::try_unwrap(e) {
+        Ok(e) => return e,
+        Err(e) => e,
+    };
+    match e.as_ref() {
+        Error::NotFound { path, .. } => Error::NotFound {
+            path: path.clone(),
+            source: Box::new(e.clone()),
+        },
+        Error::Precondition { path, .. } => Error::Precondition {
+            path: path.clone(),
+            source: Box::new(e.clone()),
+        },
+        Error::NotModified { path, .. } => Error::NotModified {
+            path: path.clone(),
+            source: Box::new(e.clone()),
+        },
+        _ => Error::Generic {
+            store: "ReadThroughCache",
+            source: Box::new(e),
+        },
+    }
+}
@@ -0,0 +1,189 @@
+//! Cache of listing results
+//!
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use moka::future::Cache;
+use object_store::{path::Path, ListResult, ObjectMeta};
+
+use crate::{paging::metadata_weight, Error, Result};
+
+/// Memory used by the cached listings of each kind, in bytes.
+const LISTING_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Caches the results of `list` and `list_with_delimiter` by prefix.
+#[derive(Debug)]
+pub(super) struct ListingCache {
+    lists: Cache<Path, Arc<Vec<ObjectMeta>>>,
+    delimited: Cache<Path, Arc<ListResult>>,
+
+    /// Number of invalidations so far. A listing loaded while one happened
+    /// may miss the object written, and is dropped.
+    epoch: AtomicU64,
+}
+
+impl ListingCache {
+    pub(super) fn new(time_to_live: Duration) -> Self {
+        Self {
+            lists: Cache::builder()
+                .weigher(|prefix: &Path, objects: &Arc<Vec<ObjectMeta>>| {
+                    weight(prefix, objects, &[])
+                })
+                .max_capacity(LISTING_CACHE_SIZE)
+                .time_to_live(time_to_live)
+                .build(),
+            delimited: Cache::builder()
+                .weigher(|prefix: &Path, result: &Arc<ListResult>| {
+                    weight(prefix, &result.objects, &result.common_prefixes)
+                })
+                .max_capacity(LISTING_CACHE_SIZE)
+                .time_to_live(time_to_live)
+                .build(),
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    /// The recursive listing of `prefix`, loaded by `loader` on a miss.
+    pub(super) async fn list(
+        &self,
+        prefix: &Path,
+        loader: impl Future<Output = Result<Vec<ObjectMeta>>>,
+    ) -> Result<Arc<Vec<ObjectMeta>>> {
+        load(&self.lists, &self.epoch, prefix, loader).await
+    }
+
+    /// The listing of `prefix` with delimiter, loaded by `loader` on a miss.
+    pub(super) async fn list_with_delimiter(
+        &self,
+        prefix: &Path,
+        loader: impl Future<Output = Result<ListResult>>,
+    ) -> Result<Arc<ListResult>> {
+        load(&self.delimited, &self.epoch, prefix, loader).await
+    }
+
+    /// Drop the listings that may include `location`, i.e. those of its
+    /// ancestors.
+    pub(super) async fn invalidate(&self, location: &Path) {
+        // Before the listings are dropped, so that those being loaded are
+        // dropped once inserted.
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+        let mut prefix = Path::default();
+        self.invalidate_prefix(&prefix).await;
+        for part in location.parts() {
+            prefix = prefix.child(part);
+            self.invalidate_prefix(&prefix).await;
+        }
+    }
+
+    async fn invalidate_prefix(&self, prefix: &Path) {
+        self.lists.invalidate(prefix).await;
+        self.delimited.invalidate(prefix).await;
+    }
+}
+
+/// Get the listing of `prefix` from `cache`, loaded by `loader` on a miss.
+///
+/// A listing loaded while `epoch` changed is returned, but not kept.
+async fn load<T: Send + Sync + 'static>(
+    cache: &Cache<Path, Arc<T>>,
+    epoch: &AtomicU64,
+    prefix: &Path,
+    loader: impl Future<Output = Result<T>>,
+) -> Result<Arc<T>> {
+    let mut started = None;
+    let result = cache
+        .try_get_with_by_ref(prefix, async {
+            started = Some(epoch.load(Ordering::Acquire));
+            loader.await.map(Arc::new)
+        })
+        .await
+        .map_err(|e| unshare(prefix, e))?;
+    if started.is_some_and(|started| started != epoch.load(Ordering::Acquire)) {
+        cache.invalidate(prefix).await;
+    }
+    Ok(result)
+}
+
+/// Memory used by a listing of `prefix`, in bytes.
+fn weight(prefix: &Path, objects: &[ObjectMeta], common_prefixes: &[Path]) -> u32 {
+    let size = prefix.as_ref().len() as u64
+        + objects
+            .iter()
+            .map(|meta| metadata_weight(meta) as u64)
+            .sum::<u64>()
+        + common_prefixes
+            .iter()
+            .map(|prefix| (std::mem::size_of::<Path>() + prefix.as_ref().len()) as u64)
+            .sum::<u64>();
+    size.try_into().unwrap_or(u32::MAX)
+}
+
+fn unshare(prefix: &Path, e: Arc<Error>) -> Error {
+    match e.as_ref() {
+        Error::NotFound { .. } => Error::NotFound {
+            path: prefix.to_string(),
+            source: Box::new(e),
+        },
+        _ => Error::Generic {
+            store: "ReadThroughCache",
+            source: Box::new(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    fn meta(location: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(location),
+            last_modified: Default::default(),
+            size: 10,
+            e_tag: None,
+            version: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listing_cache() {
+        let cache = ListingCache::new(Duration::from_secs(60));
+        let prefix = Path::from("some");
+        let loads = AtomicUsize::new(0);
+        let list = |invalidate: bool| {
+            let (cache, loads) = (&cache, &loads);
+            async move {
+                loads.fetch_add(1, Ordering::SeqCst);
+                if invalidate {
+                    // A write to the prefix while it is listed.
+                    cache.invalidate(&Path::from("some/new")).await;
+                }
+                Ok(vec![meta("some/file")])
+            }
+        };
+
+        // Listings that overlap an invalidation are not kept.
+        let objects = cache.list(&prefix, list(true)).await.unwrap();
+        assert_eq!(objects.len(), 1);
+        cache.list(&prefix, list(false)).await.unwrap();
+        cache.list(&prefix, list(false)).await.unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        // Listings are weighed by their size.
+        cache.lists.run_pending_tasks().await;
+        assert_eq!(
+            cache.lists.weighted_size(),
+            weight(&prefix, &objects, &[]) as u64
+        );
+        assert!(cache.lists.weighted_size() > metadata_weight(&objects[0]) as u64);
+    }
+}
@@ -0,0 +1,96 @@
+//! Multipart uploads written through the cache
+//!
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::warn;
+use object_store::{path::Path, MultipartUpload, PutPayload, PutResult, UploadPart};
+
+use super::{listing::ListingCache, write_through};
+use crate::{paging::PageCache, Result};
+
+/// A [`MultipartUpload`] that updates the caches once completed.
+///
+/// Without buffered parts, the cached pages and metadata of the object are
+/// dropped again once the upload completes.
+///
+/// With write-through and a buffer limit, parts are kept in memory until the
+/// upload completes or is aborted, and the object is then inserted into the
+/// cache. If they grow larger than the limit, they are dropped and the object
+/// is not cached.
+#[derive(Debug)]
+pub(super) struct CachingMultipartUpload<C: PageCache> {
+    inner: Box<dyn MultipartUpload>,
+    cache: Arc<C>,
+    location: Path,
+
+    /// Uploaded parts in order, `None` without write-through or once they
+    /// outgrew `buffer_limit`.
+    parts: Option<Vec<Bytes>>,
+    size: usize,
+    buffer_limit: usize,
+
+    listings: Option<Arc<ListingCache>>,
+}
+
+impl<C: PageCache> CachingMultipartUpload<C> {
+    pub(super) fn new(
+        inner: Box<dyn MultipartUpload>,
+        cache: Arc<C>,
+        location: Path,
+        buffer_limit: Option<usize>,
+        listings: Option<Arc<ListingCache>>,
+    ) -> Self {
+        Self {
+            inner,
+            cache,
+            location,
+            parts: buffer_limit.map(|_| Vec::new()),
+            size: 0,
+            buffer_limit: buffer_limit.unwrap_or(0),
+            listings,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: PageCache> MultipartUpload for CachingMultipartUpload<C> {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        self.size += data.content_length();
+        if self.size > self.buffer_limit {
+            self.parts = None;
+        }
+        if let Some(parts) = &mut self.parts {
+            parts.extend(data.iter().cloned());
+        }
+        self.inner.put_part(data)
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let result = self.inner.complete().await;
+        if let Some(listings) = &self.listings {
+            listings.invalidate(&self.location).await;
+        }
+        let result = result?;
+        match self.parts.take() {
+            Some(parts) => {
+                if let Err(e) =
+                    write_through(self.cache.as_ref(), &self.location, parts, &result).await
+                {
+                    warn!("failed to write {} through the cache: {e}", self.location);
+                }
+            }
+            // Reads racing with the upload may have cached the previous
+            // version, or recorded the path as missing.
+            None => self.cache.invalidate(&self.location).await?,
+        }
+        Ok(result)
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.parts = None;
+        self.inner.abort().await
+    }
+}
@@ -0,0 +1,170 @@
+//! Background loading of pages into the cache
+//!
+
+use std::sync::Arc;
+
+use futures::{stream, Future, StreamExt, TryStreamExt};
+use log::warn;
+use object_store::{path::Path, ObjectStore};
+use tokio::task::JoinHandle;
+
+use super::coalesce::PageLoader;
+use crate::{paging::PageCache, stats::CacheStats, Error, Result};
+
+/// Handle of a prefetch running in the background.
+#[derive(Debug)]
+pub struct PrefetchHandle {
+    handle: JoinHandle<Result<()>>,
+}
+
+impl PrefetchHandle {
+    pub(super) fn spawn(future: impl Future<Output = Result<()>> + Send + 'static) -> Self {
+        Self {
+            handle: tokio::spawn(future),
+        }
+    }
+
+    /// Wait for the prefetch to finish.
+    ///
+    /// Fails with the first error met while loading, or if it was cancelled.
+    pub async fn wait(self) -> Result<()> {
+        self.handle.await.map_err(|e| Error::Generic {
+            store: "ReadThroughCache",
+            source: Box::new(e),
+        })?
+    }
+
+    /// Stop the prefetch. Pages already loaded stay in the cache.
+    pub fn cancel(&self) {
+        self.handle.abort();
+    }
+
+    /// Whether the prefetch finished, or was cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Load the pages `page_ids` of `location`, in ascending order, into the cache.
+///
+/// Pages past the end of the object are ignored.
+pub(super) async fn prefetch<C, I>(
+    store: Arc<dyn ObjectStore>,
+    cache: Arc<C>,
+    stats: Arc<dyn CacheStats>,
+    location: Path,
+    page_ids: I,
+    parallelism: usize,
+    coalesce_limit: Option<usize>,
+) -> Result<()>
+where
+    C: PageCache,
+    I: IntoIterator<Item = usize>,
+{
+    let meta = cache.head(&location, store.head(&location)).await?;
+    let num_pages = meta.size.div_ceil(cache.page_size());
+    let page_ids = page_ids
+        .into_iter()
+        .filter(|&page_id| page_id < num_pages)
+        .collect::<Vec<_>>();
+    let loader = PageLoader::new(
+        store.as_ref(),
+        cache.as_ref(),
+        stats.as_ref(),
+        &meta,
+        page_ids.iter().copied(),
+        coalesce_limit,
+    )
+    .await;
+
+    stream::iter(page_ids)
+        .map(Ok)
+        .try_for_each_concurrent(parallelism, |page_id| {
+            let (loader, cache, stats, location) = (&loader, &cache, &stats, &location);
+            async move {
+                cache
+                    .get_with_latency(location, page_id as u32, async {
+                        stats.inc_total_prefetches();
+                        loader.load(page_id).await
+                    })
+                    .await?;
+                Ok(())
+            }
+        })
+        .await
+}
+
+/// Load the objects under `prefix` into the cache, until `max_bytes` are loaded.
+///
+/// The missing pages of each object are loaded with one inner read per run of
+/// consecutive pages, as with [`prefetch`], with at most `concurrency` pages
+/// loaded at a time.
+pub(super) async fn warm_up<C: PageCache>(
+    store: Arc<dyn ObjectStore>,
+    cache: Arc<C>,
+    stats: Arc<dyn CacheStats>,
+    prefix: Option<Path>,
+    max_bytes: usize,
+    concurrency: usize,
+    coalesce_limit: Option<usize>,
+) -> Result<()> {
+    let page_size = cache.page_size();
+    let mut remaining = max_bytes;
+    let mut objects = vec![];
+    let mut listed = store.list(prefix.as_ref());
+    while remaining > 0 {
+        let Some(meta) = listed.try_next().await? else {
+            break;
+        };
+        let location = meta.location.clone();
+        // The listing already has the metadata, no need for another request.
+        let meta = match cache.head(&location, async { Ok(meta) }).await {
+            Ok(meta) => meta,
+            Err(e) => {
+                warn!("failed to warm up {location}: {e}");
+                continue;
+            }
+        };
+        let len = std::cmp::min(meta.size, remaining);
+        remaining -= len;
+        objects.push((meta, len.div_ceil(page_size)));
+    }
+
+    let mut loaders = vec![];
+    for (meta, num_pages) in &objects {
+        let loader = PageLoader::new(
+            store.as_ref(),
+            cache.as_ref(),
+            stats.as_ref(),
+            meta,
+            0..*num_pages,
+            coalesce_limit,
+        )
+        .await;
+        loaders.push(loader);
+    }
+
+    let pages = objects
+        .iter()
+        .zip(&loaders)
+        .flat_map(|((meta, num_pages), loader)| {
+            (0..*num_pages).map(move |page_id| (meta, loader, page_id))
+        });
+    stream::iter(pages)
+        .for_each_concurrent(concurrency, |(meta, loader, page_id)| {
+            let (cache, stats) = (&cache, &stats);
+            async move {
+                let res = cache
+                    .get_with_latency(&meta.location, page_id as u32, async {
+                        stats.inc_total_prefetches();
+                        loader.load(page_id).await
+                    })
+                    .await;
+                if let Err(e) = res {
+                    warn!("failed to warm up page {page_id} of {}: {e}", meta.location);
+                }
+            }
+        })
+        .await;
+    Ok(())
+}
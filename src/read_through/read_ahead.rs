@@ -0,0 +1,151 @@
+//! Sequential access detection for read-ahead
+//!
+
+use std::{ops::Range, time::Duration};
+
+use moka::future::Cache;
+use object_store::path::Path;
+
+/// Idle time after which the access pattern of a path is forgotten.
+const STREAM_TIME_TO_IDLE: Duration = Duration::from_secs(60);
+
+/// Sequential read-ahead settings of a [`ReadThroughCache`](crate::ReadThroughCache).
+///
+/// Once two consecutive reads of a path are sequential, the next
+/// `initial_pages` pages are loaded in the background. The window doubles
+/// while reads stay sequential, up to `max_pages`, and is reset by the first
+/// read that is not.
+#[derive(Debug, Clone)]
+pub struct ReadAhead {
+    /// Pages prefetched once a sequential read is detected.
+    pub initial_pages: usize,
+
+    /// Maximum number of pages prefetched ahead of the last read.
+    pub max_pages: usize,
+
+    /// Maximum number of paths whose access pattern is tracked.
+    pub max_streams: u64,
+}
+
+impl Default for ReadAhead {
+    fn default() -> Self {
+        Self {
+            initial_pages: 4,
+            max_pages: 64,
+            max_streams: 1024,
+        }
+    }
+}
+
+/// Access pattern of one path.
+#[derive(Debug, Clone, Default)]
+struct Stream {
+    /// End of the last read.
+    end: usize,
+
+    /// Read-ahead window, in pages. Zero while the access is not sequential.
+    window: usize,
+
+    /// Pages up to this one, exclusive, were already prefetched.
+    prefetched_until: usize,
+
+    /// Pages to prefetch after the last read.
+    prefetch: Range<usize>,
+}
+
+/// Tracks the reads of each path to detect sequential access.
+#[derive(Debug)]
+pub(super) struct ReadAheadTracker {
+    options: ReadAhead,
+    page_size: usize,
+    streams: Cache<Path, Stream>,
+}
+
+impl ReadAheadTracker {
+    pub(super) fn new(options: ReadAhead, page_size: usize) -> Self {
+        let streams = Cache::builder()
+            .max_capacity(options.max_streams)
+            .time_to_idle(STREAM_TIME_TO_IDLE)
+            .build();
+        Self {
+            options,
+            page_size,
+            streams,
+        }
+    }
+
+    /// Record a read of `range`, and return the page ids to prefetch.
+    ///
+    /// A read is sequential if it starts in the page where the previous
+    /// read ended, or in the next one.
+    pub(super) async fn on_read(&self, location: &Path, range: &Range<usize>) -> Range<usize> {
+        if range.is_empty() {
+            return 0..0;
+        }
+        let page_size = self.page_size;
+        let options = &self.options;
+        let entry = self
+            .streams
+            .entry_by_ref(location)
+            .and_upsert_with(|prev| {
+                let prev = prev.map(|entry| entry.into_value()).unwrap_or_default();
+                let start_page = range.start / page_size;
+                let last_page = (range.end - 1) / page_size;
+                let prev_page = prev.end.saturating_sub(1) / page_size;
+                let sequential = prev.end > 0 && (prev_page..=prev_page + 1).contains(&start_page);
+
+                let window = match (sequential, prev.window) {
+                    (false, _) => 0,
+                    (true, 0) => options.initial_pages,
+                    (true, window) => std::cmp::min(window * 2, options.max_pages),
+                };
+                let from = std::cmp::max(last_page + 1, prev.prefetched_until);
+                let until = if window == 0 {
+                    from
+                } else {
+                    last_page + 1 + window
+                };
+                let prefetch = from..std::cmp::max(from, until);
+                std::future::ready(Stream {
+                    end: range.end,
+                    window,
+                    prefetched_until: if window == 0 { 0 } else { prefetch.end },
+                    prefetch,
+                })
+            })
+            .await;
+        entry.into_value().prefetch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_window_adapts() {
+        let tracker = ReadAheadTracker::new(
+            ReadAhead {
+                initial_pages: 2,
+                max_pages: 8,
+                ..Default::default()
+            },
+            10,
+        );
+        let location = Path::from("a");
+
+        assert_eq!(tracker.on_read(&location, &(0..10)).await, 1..1);
+        assert_eq!(tracker.on_read(&location, &(10..20)).await, 2..4);
+        // Pages already prefetched are not fetched again.
+        assert_eq!(tracker.on_read(&location, &(20..30)).await, 4..7);
+        assert_eq!(tracker.on_read(&location, &(30..40)).await, 7..12);
+        assert_eq!(tracker.on_read(&location, &(40..50)).await, 12..13);
+
+        // Random access shuts read-ahead off, until reads are sequential again.
+        assert_eq!(tracker.on_read(&location, &(500..510)).await, 51..51);
+        assert_eq!(tracker.on_read(&location, &(510..515)).await, 52..54);
+
+        // Other paths are tracked separately.
+        assert_eq!(tracker.on_read(&Path::from("b"), &(10..20)).await, 2..2);
+    }
+}
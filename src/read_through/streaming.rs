@@ -0,0 +1,214 @@
+//! Streaming reads through the cache
+//!
+
+use std::{
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{stream, stream::BoxStream, StreamExt};
+use object_store::{ObjectMeta, ObjectStore};
+
+use super::{admission::Admission, pinned_options};
+use crate::{paging::PageCache, stats::CacheStats, Error, Result};
+
+/// Stream `range` of the object described by `meta`, page by page.
+///
+/// Cached pages are served from the cache. A run of missing pages is read
+/// with one streaming request to the inner store, and each page is inserted
+/// into the cache as soon as its bytes arrive. Pages are only read when the
+/// consumer polls for them.
+///
+/// Missing pages are only inserted into the cache if `admission` admits them.
+pub(super) fn page_stream<C: PageCache>(
+    store: Arc<dyn ObjectStore>,
+    cache: Arc<C>,
+    stats: Arc<dyn CacheStats>,
+    meta: ObjectMeta,
+    range: Range<usize>,
+    admission: Admission,
+) -> BoxStream<'static, Result<Bytes>> {
+    let page_size = cache.page_size();
+    let state = PageStream {
+        store,
+        cache,
+        stats,
+        meta,
+        page_id: range.start / page_size,
+        range,
+        admission,
+        inner: None,
+    };
+    stream::try_unfold(state, |mut state| async move {
+        match state.next_page().await {
+            Ok(page) => Ok(page.map(|page| (page, state))),
+            // The object changed since its metadata was cached, the pages
+            // read so far can't be recalled, but the next read reloads it.
+            Err(e @ Error::Precondition { .. }) => {
+                state.cache.invalidate(&state.meta.location).await?;
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .boxed()
+}
+
+struct PageStream<C: PageCache> {
+    store: Arc<dyn ObjectStore>,
+    cache: Arc<C>,
+    stats: Arc<dyn CacheStats>,
+    meta: ObjectMeta,
+    range: Range<usize>,
+    admission: Admission,
+
+    /// Next page to yield.
+    page_id: usize,
+
+    /// Inner read, open while consecutive pages are missing.
+    inner: Option<InnerStream>,
+}
+
+impl<C: PageCache> PageStream<C> {
+    async fn next_page(&mut self) -> Result<Option<Bytes>> {
+        let Self {
+            store,
+            cache,
+            stats,
+            meta,
+            range,
+            admission,
+            page_id,
+            inner,
+        } = self;
+        let page_size = cache.page_size();
+        let offset = *page_id * page_size;
+        if offset >= range.end {
+            return Ok(None);
+        }
+        let page_end = std::cmp::min(offset + page_size, meta.size);
+        let range_in_page = std::cmp::max(offset, range.start) - offset
+            ..std::cmp::min(offset + page_size, range.end) - offset;
+        let location = &meta.location;
+        let id = *page_id as u32;
+        *page_id += 1;
+
+        stats.inc_total_reads();
+        if let Some(page) = cache.get(location, id).await? {
+            // A later miss opens a new inner read from its own offset.
+            *inner = None;
+            return Ok(Some(page.slice(range_in_page)));
+        }
+
+        let load = async {
+            stats.inc_total_misses();
+            let stream = match inner {
+                Some(stream) if stream.end >= page_end => stream,
+                _ => {
+                    // Read up to the end of the run of missing pages, whole
+                    // pages so that they can be cached.
+                    let last_page = (range.end - 1) / page_size;
+                    let mut next = id as usize + 1;
+                    while next <= last_page && !cache.contains(location, next as u32).await {
+                        next += 1;
+                    }
+                    let end = std::cmp::min(next * page_size, meta.size);
+                    let stream =
+                        InnerStream::open(store.as_ref(), stats.as_ref(), meta, offset..end)
+                            .await?;
+                    inner.insert(stream)
+                }
+            };
+            // A read of the page alone would take the latency of the inner
+            // read, and the time to receive the page.
+            let started = Instant::now();
+            let data = stream.read(offset..page_end).await?;
+            Ok((data, stream.latency + started.elapsed()))
+        };
+        let page = if admission.admits(stats.as_ref(), location, id as usize) {
+            cache.get_with_latency(location, id, load).await?
+        } else {
+            load.await?.0
+        };
+        Ok(Some(page.slice(range_in_page)))
+    }
+}
+
+/// A streaming read from the inner store.
+struct InnerStream {
+    stream: BoxStream<'static, Result<Bytes>>,
+
+    /// Offset in the object of the first byte of `buf`.
+    offset: usize,
+    buf: Bytes,
+
+    /// End of the read, in the object.
+    end: usize,
+
+    /// Time to open the read.
+    latency: Duration,
+}
+
+impl InnerStream {
+    async fn open(
+        store: &dyn ObjectStore,
+        stats: &dyn CacheStats,
+        meta: &ObjectMeta,
+        range: Range<usize>,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let len = range.len();
+        let Range { start: offset, end } = range.clone();
+        let result = store
+            .get_opts(&meta.location, pinned_options(meta, range))
+            .await?;
+        let latency = start.elapsed();
+        stats.record_fetch(latency, len);
+        Ok(Self {
+            stream: result.into_stream(),
+            offset,
+            buf: Bytes::new(),
+            end,
+            latency,
+        })
+    }
+
+    /// Read `range` of the object, skipping the bytes before it.
+    async fn read(&mut self, range: Range<usize>) -> Result<Bytes> {
+        let mut out = BytesMut::with_capacity(range.len());
+        while self.offset < range.end {
+            if self.buf.is_empty() {
+                self.buf = match self.stream.next().await {
+                    Some(chunk) => chunk?,
+                    None => {
+                        return Err(Error::Generic {
+                            store: "ReadThroughCache",
+                            source: format!(
+                                "unexpected end of stream at offset {}, expected {}",
+                                self.offset, range.end
+                            )
+                            .into(),
+                        })
+                    }
+                };
+                continue;
+            }
+            if self.offset < range.start {
+                let n = std::cmp::min(self.buf.len(), range.start - self.offset);
+                self.buf.advance(n);
+                self.offset += n;
+                continue;
+            }
+
+            // Always copy, a slice of the chunk would keep the whole chunk
+            // alive for as long as the page is cached.
+            let n = std::cmp::min(self.buf.len(), range.end - self.offset);
+            out.extend_from_slice(&self.buf[..n]);
+            self.buf.advance(n);
+            self.offset += n;
+        }
+        Ok(out.freeze())
+    }
+}
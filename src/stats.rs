@@ -3,6 +3,7 @@
 use std::{
     fmt::Debug,
     sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 use log::warn;
@@ -19,6 +20,182 @@ pub trait CacheReadStats: Sync + Send + Debug {
 
     /// Increase total hits by 1.
     fn inc_total_misses(&self);
+
+    /// Total pages loaded ahead of being read.
+    ///
+    /// Not tracked by default.
+    fn total_prefetches(&self) -> u64 {
+        0
+    }
+
+    /// Increase total prefetches by 1.
+    fn inc_total_prefetches(&self) {}
+
+    /// Total missing pages inserted after passing the admission filter.
+    ///
+    /// Not tracked by default.
+    fn total_admitted(&self) -> u64 {
+        0
+    }
+
+    /// Increase total admitted by 1.
+    fn inc_total_admitted(&self) {}
+
+    /// Total missing pages served without being inserted, rejected by the
+    /// admission filter.
+    ///
+    /// Not tracked by default.
+    fn total_rejected(&self) -> u64 {
+        0
+    }
+
+    /// Increase total rejected by 1.
+    fn inc_total_rejected(&self) {}
+
+    /// Record the latency of a range read, by how much of it was cached.
+    ///
+    /// Latencies are not tracked by default, and their histograms stay empty.
+    fn record_read_latency(&self, _outcome: ReadOutcome, _latency: Duration) {}
+
+    /// Latency of range reads with `outcome`, in nanoseconds.
+    fn read_latency(&self, _outcome: ReadOutcome) -> HistogramSnapshot {
+        AtomicHistogram::new(LATENCY_BUCKETS).snapshot()
+    }
+
+    /// Record a read issued to the inner store to load missed pages.
+    fn record_fetch(&self, _latency: Duration, _size: usize) {}
+
+    /// Latency of the reads issued to the inner store, in nanoseconds.
+    fn fetch_latency(&self) -> HistogramSnapshot {
+        AtomicHistogram::new(LATENCY_BUCKETS).snapshot()
+    }
+
+    /// Size of the reads issued to the inner store, in bytes.
+    fn fetch_size(&self) -> HistogramSnapshot {
+        AtomicHistogram::new(SIZE_BUCKETS).snapshot()
+    }
+}
+
+/// How much of a range read was served by the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadOutcome {
+    /// All pages were cached.
+    Hit,
+    /// No page was cached.
+    Miss,
+    /// Some pages were cached.
+    PartialHit,
+}
+
+impl ReadOutcome {
+    /// Outcome of a read of `pages` pages, of which `misses` were not cached.
+    pub fn of(pages: usize, misses: usize) -> Self {
+        if misses == 0 {
+            Self::Hit
+        } else if misses >= pages {
+            Self::Miss
+        } else {
+            Self::PartialHit
+        }
+    }
+}
+
+/// Upper bounds of the latency histogram buckets, in nanoseconds.
+pub const LATENCY_BUCKETS: &[u64] = &[
+    10_000,
+    50_000,
+    100_000,
+    250_000,
+    500_000,
+    1_000_000,
+    2_500_000,
+    5_000_000,
+    10_000_000,
+    25_000_000,
+    50_000_000,
+    100_000_000,
+    250_000_000,
+    500_000_000,
+    1_000_000_000,
+    2_500_000_000,
+    5_000_000_000,
+    10_000_000_000,
+];
+
+/// Upper bounds of the size histogram buckets, in bytes.
+pub const SIZE_BUCKETS: &[u64] = &[
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+    64 << 20,
+    256 << 20,
+];
+
+/// Histogram with fixed buckets and atomic counters.
+#[derive(Debug)]
+pub struct AtomicHistogram {
+    bounds: &'static [u64],
+
+    /// One counter per bucket, plus one for values above the last bound.
+    counts: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl AtomicHistogram {
+    /// Create a histogram with buckets of upper bounds `bounds`, in ascending order.
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one value.
+    pub fn record(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Copy the current counters.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            bounds: self.bounds,
+            counts: self
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Acquire))
+                .collect(),
+            sum: self.sum.load(Ordering::Acquire),
+        }
+    }
+}
+
+/// Point-in-time copy of a histogram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Upper bounds of the buckets, inclusive.
+    pub bounds: &'static [u64],
+
+    /// Number of values in each bucket. The last one counts the values
+    /// above the last bound.
+    pub counts: Vec<u64>,
+
+    /// Sum of all values.
+    pub sum: u64,
+}
+
+impl HistogramSnapshot {
+    /// Number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
 }
 
 pub trait CacheCapacityStats {
@@ -33,6 +210,38 @@ pub trait CacheCapacityStats {
     fn inc_usage(&self, val: u64);
 
     fn sub_usage(&self, val: u64);
+
+    /// Memory used by cached object metadata, in bytes.
+    ///
+    /// Not tracked by default.
+    fn metadata_usage(&self) -> u64 {
+        0
+    }
+
+    fn inc_metadata_usage(&self, _val: u64) {}
+
+    fn sub_metadata_usage(&self, _val: u64) {}
+
+    /// Bytes of the pinned pages, not part of the usage.
+    ///
+    /// Not tracked by default.
+    fn pinned_usage(&self) -> u64 {
+        0
+    }
+
+    fn inc_pinned_usage(&self, _val: u64) {}
+
+    fn sub_pinned_usage(&self, _val: u64) {}
+
+    /// Total entries evicted to make room for others, or expired.
+    ///
+    /// Not tracked by default.
+    fn total_evictions(&self) -> u64 {
+        0
+    }
+
+    /// Increase total evictions by 1.
+    fn inc_total_evictions(&self) {}
 }
 
 pub trait CacheStats: CacheCapacityStats + CacheReadStats {}
@@ -41,8 +250,19 @@ pub trait CacheStats: CacheCapacityStats + CacheReadStats {}
 pub struct AtomicIntCacheStats {
     total_reads: AtomicU64,
     total_misses: AtomicU64,
+    total_prefetches: AtomicU64,
+    total_admitted: AtomicU64,
+    total_rejected: AtomicU64,
     max_capacity: AtomicU64,
     capacity_usage: AtomicU64,
+    metadata_usage: AtomicU64,
+    pinned_usage: AtomicU64,
+    total_evictions: AtomicU64,
+    hit_latency: AtomicHistogram,
+    miss_latency: AtomicHistogram,
+    partial_hit_latency: AtomicHistogram,
+    fetch_latency: AtomicHistogram,
+    fetch_size: AtomicHistogram,
 }
 
 impl AtomicIntCacheStats {
@@ -50,8 +270,27 @@ impl AtomicIntCacheStats {
         Self {
             total_misses: AtomicU64::new(0),
             total_reads: AtomicU64::new(0),
+            total_prefetches: AtomicU64::new(0),
+            total_admitted: AtomicU64::new(0),
+            total_rejected: AtomicU64::new(0),
             max_capacity: AtomicU64::new(0),
             capacity_usage: AtomicU64::new(0),
+            metadata_usage: AtomicU64::new(0),
+            pinned_usage: AtomicU64::new(0),
+            total_evictions: AtomicU64::new(0),
+            hit_latency: AtomicHistogram::new(LATENCY_BUCKETS),
+            miss_latency: AtomicHistogram::new(LATENCY_BUCKETS),
+            partial_hit_latency: AtomicHistogram::new(LATENCY_BUCKETS),
+            fetch_latency: AtomicHistogram::new(LATENCY_BUCKETS),
+            fetch_size: AtomicHistogram::new(SIZE_BUCKETS),
+        }
+    }
+
+    fn read_histogram(&self, outcome: ReadOutcome) -> &AtomicHistogram {
+        match outcome {
+            ReadOutcome::Hit => &self.hit_latency,
+            ReadOutcome::Miss => &self.miss_latency,
+            ReadOutcome::PartialHit => &self.partial_hit_latency,
         }
     }
 }
@@ -78,6 +317,52 @@ impl CacheReadStats for AtomicIntCacheStats {
     fn inc_total_misses(&self) {
         self.total_misses.fetch_add(1, Ordering::Relaxed);
     }
+
+    fn total_prefetches(&self) -> u64 {
+        self.total_prefetches.load(Ordering::Acquire)
+    }
+
+    fn inc_total_prefetches(&self) {
+        self.total_prefetches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn total_admitted(&self) -> u64 {
+        self.total_admitted.load(Ordering::Acquire)
+    }
+
+    fn inc_total_admitted(&self) {
+        self.total_admitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn total_rejected(&self) -> u64 {
+        self.total_rejected.load(Ordering::Acquire)
+    }
+
+    fn inc_total_rejected(&self) {
+        self.total_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_read_latency(&self, outcome: ReadOutcome, latency: Duration) {
+        self.read_histogram(outcome)
+            .record(latency.as_nanos() as u64);
+    }
+
+    fn read_latency(&self, outcome: ReadOutcome) -> HistogramSnapshot {
+        self.read_histogram(outcome).snapshot()
+    }
+
+    fn record_fetch(&self, latency: Duration, size: usize) {
+        self.fetch_latency.record(latency.as_nanos() as u64);
+        self.fetch_size.record(size as u64);
+    }
+
+    fn fetch_latency(&self) -> HistogramSnapshot {
+        self.fetch_latency.snapshot()
+    }
+
+    fn fetch_size(&self) -> HistogramSnapshot {
+        self.fetch_size.snapshot()
+    }
 }
 
 impl CacheCapacityStats for AtomicIntCacheStats {
@@ -119,6 +404,92 @@ impl CacheCapacityStats for AtomicIntCacheStats {
             warn!("error setting cache usage: {:?}", e);
         }
     }
+
+    fn metadata_usage(&self) -> u64 {
+        self.metadata_usage.load(Ordering::Acquire)
+    }
+
+    fn inc_metadata_usage(&self, val: u64) {
+        self.metadata_usage.fetch_add(val, Ordering::Relaxed);
+    }
+
+    fn sub_metadata_usage(&self, val: u64) {
+        let res =
+            self.metadata_usage
+                .fetch_update(Ordering::Acquire, Ordering::Relaxed, |current| {
+                    if current < val {
+                        warn!(
+                        "cannot decrement metadata usage. current val = {:?} and decrement = {:?}",
+                        current, val
+                    );
+                        None
+                    } else {
+                        Some(current - val)
+                    }
+                });
+        if let Err(e) = res {
+            warn!("error setting metadata usage: {:?}", e);
+        }
+    }
+
+    fn pinned_usage(&self) -> u64 {
+        self.pinned_usage.load(Ordering::Acquire)
+    }
+
+    fn inc_pinned_usage(&self, val: u64) {
+        self.pinned_usage.fetch_add(val, Ordering::Relaxed);
+    }
+
+    fn sub_pinned_usage(&self, val: u64) {
+        let res = self
+            .pinned_usage
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |current| {
+                if current < val {
+                    warn!(
+                        "cannot decrement pinned usage. current val = {:?} and decrement = {:?}",
+                        current, val
+                    );
+                    None
+                } else {
+                    Some(current - val)
+                }
+            });
+        if let Err(e) = res {
+            warn!("error setting pinned usage: {:?}", e);
+        }
+    }
+
+    fn total_evictions(&self) -> u64 {
+        self.total_evictions.load(Ordering::Acquire)
+    }
+
+    fn inc_total_evictions(&self) {
+        self.total_evictions.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl CacheStats for AtomicIntCacheStats {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram = AtomicHistogram::new(&[10, 100]);
+        for value in [0, 10, 11, 100, 1000] {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.counts, vec![2, 2, 1]);
+        assert_eq!(snapshot.count(), 5);
+        assert_eq!(snapshot.sum, 1121);
+    }
+
+    #[test]
+    fn test_read_outcome() {
+        assert_eq!(ReadOutcome::of(3, 0), ReadOutcome::Hit);
+        assert_eq!(ReadOutcome::of(3, 1), ReadOutcome::PartialHit);
+        assert_eq!(ReadOutcome::of(3, 3), ReadOutcome::Miss);
+    }
+}
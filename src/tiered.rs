@@ -0,0 +1,546 @@
+//! Two-tier [`PageCache`] implementation
+//!
+//! Combines a fast, small first tier (L1) with a slower, larger second tier
+//! (L2). Typically an [`InMemoryCache`] in front of a
+//! [`DiskCache`](crate::disk::DiskCache), so that hot pages are served at
+//! memory latency and warm pages still avoid a round trip to the object store.
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use tokio::runtime::Runtime;
+//! use ocra::{disk::DiskCache, memory::InMemoryCache, tiered::TieredCache};
+//!
+//! # let rt = Runtime::new().unwrap();
+//! # rt.block_on(async {
+//! let l2 = DiskCache::builder("/mnt/nvme/ocra", 512 * 1024 * 1024 * 1024)
+//!     .build()
+//!     .await
+//!     .unwrap();
+//! // Pages evicted from memory are demoted to disk.
+//! let cache = TieredCache::with_demotion(
+//!     InMemoryCache::with_sys_memory(0.5).page_size(1024 * 1024),
+//!     Arc::new(l2),
+//! );
+//! # })
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::{self, Future},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use log::warn;
+use object_store::{path::Path, ObjectMeta};
+
+use crate::{
+    memory::{InMemoryCache, InMemoryCacheBuilder},
+    paging::{EvictionListener, ObjectVersion, PageCache},
+    stats::CacheStats,
+    Result,
+};
+
+/// Two-tier [`PageCache`].
+///
+/// A miss in L1 checks L2 before calling the loader, and pages found in L2
+/// are promoted into L1.
+///
+/// Without demotion, loaded pages are inserted in both tiers. With demotion,
+/// loaded pages are only inserted in L1 and move to L2 once evicted from L1.
+#[derive(Debug)]
+pub struct TieredCache<L1: PageCache, L2: PageCache> {
+    l1: Arc<L1>,
+    l2: Arc<L2>,
+
+    /// Demotions of the pages evicted from L1 into L2, if enabled.
+    demotions: Option<Arc<Demotions>>,
+}
+
+impl<L1: PageCache, L2: PageCache> TieredCache<L1, L2> {
+    /// Create a [TieredCache] that inserts loaded pages in both tiers.
+    ///
+    /// Both tiers must use the same page size.
+    pub fn new(l1: Arc<L1>, l2: Arc<L2>) -> Self {
+        assert_eq!(
+            l1.page_size(),
+            l2.page_size(),
+            "both tiers must use the same page size"
+        );
+        Self {
+            l1,
+            l2,
+            demotions: None,
+        }
+    }
+
+    /// The first tier.
+    pub fn l1(&self) -> &Arc<L1> {
+        &self.l1
+    }
+
+    /// The second tier.
+    pub fn l2(&self) -> &Arc<L2> {
+        &self.l2
+    }
+
+    fn cancel_demotions(&self, location: &Path) {
+        if let Some(demotions) = &self.demotions {
+            demotions.cancel(location);
+        }
+    }
+}
+
+impl<L2: PageCache> TieredCache<InMemoryCache, L2> {
+    /// Create a [TieredCache] with an [InMemoryCache] L1 built from `l1`,
+    /// whose evicted pages are demoted into `l2`.
+    ///
+    /// Pages are written in the background, so this must be used within a
+    /// tokio runtime. Pages evicted while too many others are being written
+    /// are dropped.
+    ///
+    /// Both tiers must use the same page size.
+    pub fn with_demotion(l1: &mut InMemoryCacheBuilder, l2: Arc<L2>) -> Self {
+        let demotions = Arc::new(Demotions::new(MAX_PENDING_DEMOTIONS));
+        let l1 = l1
+            .eviction_listener(demote_into(l2.clone(), demotions.clone()))
+            .build();
+        Self {
+            demotions: Some(demotions),
+            ..Self::new(Arc::new(l1), l2)
+        }
+    }
+}
+
+/// Demotions written at most at a time.
+const MAX_PENDING_DEMOTIONS: usize = 64;
+
+#[derive(Debug, Default)]
+struct PendingDemotions {
+    count: usize,
+
+    /// Incremented when the location is invalidated or its version may have
+    /// changed, so that the pages evicted before are not demoted.
+    epoch: u64,
+}
+
+/// Demotions being written, by location.
+#[derive(Debug)]
+struct Demotions {
+    max_pending: usize,
+    state: Mutex<DemotionState>,
+}
+
+#[derive(Debug, Default)]
+struct DemotionState {
+    pending: HashMap<Path, PendingDemotions>,
+    count: usize,
+}
+
+impl Demotions {
+    fn new(max_pending: usize) -> Self {
+        Self {
+            max_pending,
+            state: Mutex::new(DemotionState::default()),
+        }
+    }
+
+    /// Start the demotion of a page of `location`, and return its epoch, or
+    /// `None` if too many demotions are pending.
+    fn start(&self, location: &Path) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.count >= self.max_pending {
+            return None;
+        }
+        state.count += 1;
+        let pending = state.pending.entry(location.clone()).or_default();
+        pending.count += 1;
+        Some(pending.epoch)
+    }
+
+    /// Whether the pages of `location` evicted at `epoch` may still be demoted.
+    fn is_current(&self, location: &Path, epoch: u64) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .pending
+            .get(location)
+            .is_some_and(|pending| pending.epoch == epoch)
+    }
+
+    /// Finish a demotion started at `epoch`, and return whether it is still
+    /// current.
+    fn finish(&self, location: &Path, epoch: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.count -= 1;
+        let Some(pending) = state.pending.get_mut(location) else {
+            return false;
+        };
+        let current = pending.epoch == epoch;
+        pending.count -= 1;
+        if pending.count == 0 {
+            state.pending.remove(location);
+        }
+        current
+    }
+
+    /// Cancel the pending demotions of `location`.
+    fn cancel(&self, location: &Path) {
+        if let Some(pending) = self.state.lock().unwrap().pending.get_mut(location) {
+            pending.epoch += 1;
+        }
+    }
+}
+
+/// Returns an [EvictionListener] that inserts evicted pages into `cache`, in
+/// the background, unless cancelled by `demotions`.
+fn demote_into<C: PageCache>(cache: Arc<C>, demotions: Arc<Demotions>) -> EvictionListener {
+    Arc::new(move |location: &Path, page_id: u32, data: Bytes| {
+        let Some(epoch) = demotions.start(location) else {
+            return;
+        };
+        let cache = cache.clone();
+        let demotions = demotions.clone();
+        let location = location.clone();
+        tokio::spawn(async move {
+            if !demotions.is_current(&location, epoch) {
+                demotions.finish(&location, epoch);
+                return;
+            }
+            if let Err(e) = cache.put(&location, page_id, data).await {
+                warn!("failed to demote page {page_id} of {location}: {e}");
+            }
+            if !demotions.finish(&location, epoch) {
+                // Cancelled while being written, the page may belong to an
+                // older version of the object.
+                if let Err(e) = cache.invalidate(&location).await {
+                    warn!("failed to invalidate {location} after demotion: {e}");
+                }
+            }
+        });
+    })
+}
+
+#[async_trait::async_trait]
+impl<L1: PageCache, L2: PageCache> PageCache for TieredCache<L1, L2> {
+    fn page_size(&self) -> usize {
+        self.l1.page_size()
+    }
+
+    /// Total capacity of both tiers.
+    fn capacity(&self) -> usize {
+        self.l1.capacity() + self.l2.capacity()
+    }
+
+    /// Total used size of both tiers.
+    fn size(&self) -> usize {
+        self.l1.size() + self.l2.size()
+    }
+
+    /// Stats of L1, the stats of L2 are available from [`Self::l2()`].
+    fn stats(&self) -> Arc<dyn CacheStats> {
+        self.l1.stats()
+    }
+
+    async fn get_with(
+        &self,
+        location: &Path,
+        page_id: u32,
+        loader: impl Future<Output = Result<Bytes>> + Send,
+    ) -> Result<Bytes> {
+        self.l1
+            .get_with(location, page_id, async {
+                if self.demotions.is_some() {
+                    match self.l2.get(location, page_id).await? {
+                        Some(data) => Ok(data),
+                        None => loader.await,
+                    }
+                } else {
+                    self.l2.get_with(location, page_id, loader).await
+                }
+            })
+            .await
+    }
+
+    async fn get_with_latency(
+        &self,
+        location: &Path,
+        page_id: u32,
+        loader: impl Future<Output = Result<(Bytes, Duration)>> + Send,
+    ) -> Result<Bytes> {
+        // L1 weighs the latency of L2, from where its pages are loaded again.
+        self.l1
+            .get_with(location, page_id, async {
+                if self.demotions.is_some() {
+                    match self.l2.get(location, page_id).await? {
+                        Some(data) => Ok(data),
+                        None => loader.await.map(|(data, _)| data),
+                    }
+                } else {
+                    self.l2.get_with_latency(location, page_id, loader).await
+                }
+            })
+            .await
+    }
+
+    async fn get_range_with(
+        &self,
+        location: &Path,
+        page_id: u32,
+        range: Range<usize>,
+        loader: impl Future<Output = Result<Bytes>> + Send,
+    ) -> Result<Bytes> {
+        assert!(range.start <= range.end && range.end <= self.page_size());
+        let bytes = self.get_with(location, page_id, loader).await?;
+        Ok(bytes.slice(range))
+    }
+
+    async fn get(&self, location: &Path, page_id: u32) -> Result<Option<Bytes>> {
+        if let Some(data) = self.l1.get(location, page_id).await? {
+            return Ok(Some(data));
+        }
+        let Some(data) = self.l2.get(location, page_id).await? else {
+            return Ok(None);
+        };
+        self.l1.put(location, page_id, data.clone()).await?;
+        Ok(Some(data))
+    }
+
+    async fn contains(&self, location: &Path, page_id: u32) -> bool {
+        self.l1.contains(location, page_id).await || self.l2.contains(location, page_id).await
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        page_id: u32,
+        range: Range<usize>,
+    ) -> Result<Option<Bytes>> {
+        Ok(self
+            .get(location, page_id)
+            .await?
+            .map(|bytes| bytes.slice(range)))
+    }
+
+    async fn head(
+        &self,
+        location: &Path,
+        loader: impl Future<Output = Result<ObjectMeta>> + Send,
+    ) -> Result<ObjectMeta> {
+        let mut loaded = false;
+        let meta = self
+            .l1
+            .head(location, async {
+                // Not through the metadata of L2, which may be older than the
+                // metadata L1 just dropped.
+                let meta = loader.await?;
+                loaded = true;
+                self.cancel_demotions(location);
+                let cached = self
+                    .l2
+                    .head(location, future::ready(Ok(meta.clone())))
+                    .await?;
+                if ObjectVersion::of(&cached) != ObjectVersion::of(&meta) {
+                    // Drop the stale metadata of L2, with its pages.
+                    self.l2.invalidate(location).await?;
+                    self.l2
+                        .head(location, future::ready(Ok(meta.clone())))
+                        .await?;
+                }
+                Ok(meta)
+            })
+            .await?;
+        if loaded {
+            // The pages L1 evicts until it binds the new version.
+            self.cancel_demotions(location);
+        }
+        Ok(meta)
+    }
+
+    async fn put(&self, location: &Path, page_id: u32, data: Bytes) -> Result<()> {
+        if self.demotions.is_none() {
+            self.l2.put(location, page_id, data.clone()).await?;
+        }
+        self.l1.put(location, page_id, data).await
+    }
+
+    async fn invalidate(&self, location: &Path) -> Result<()> {
+        self.cancel_demotions(location);
+        self.l1.invalidate(location).await?;
+        self.l2.invalidate(location).await
+    }
+
+    /// Pin the pages in L1.
+    async fn pin(&self, location: &Path, pages: Range<u32>) -> Result<()> {
+        self.l1.pin(location, pages).await
+    }
+
+    async fn unpin(&self, location: &Path, pages: Range<u32>) -> Result<()> {
+        self.l1.unpin(location, pages).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tempfile::tempdir;
+
+    use crate::disk::DiskCache;
+
+    const PAGE_SIZE: usize = 512;
+
+    async fn load<C: PageCache>(
+        cache: &C,
+        location: &Path,
+        page_id: u32,
+        miss: &AtomicUsize,
+    ) -> Bytes {
+        cache
+            .get_with(location, page_id, async {
+                miss.fetch_add(1, Ordering::SeqCst);
+                Ok(Bytes::from(vec![page_id as u8; PAGE_SIZE]))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_l2_hit_is_promoted() {
+        let tmp_dir = tempdir().unwrap();
+        let l1 = Arc::new(InMemoryCache::new(4 * PAGE_SIZE, PAGE_SIZE));
+        let l2 = DiskCache::builder(tmp_dir.path(), 16 * PAGE_SIZE)
+            .page_size(PAGE_SIZE)
+            .build()
+            .await
+            .unwrap();
+        let cache = TieredCache::new(l1.clone(), Arc::new(l2));
+        let location = Path::from("some/file.lance");
+        let miss = AtomicUsize::new(0);
+
+        load(&cache, &location, 0, &miss).await;
+        assert_eq!(miss.load(Ordering::SeqCst), 1);
+        assert!(cache.l2().get(&location, 0).await.unwrap().is_some());
+
+        // Drop the page from L1, it is then served from L2.
+        l1.invalidate(&location).await.unwrap();
+        assert!(l1.get(&location, 0).await.unwrap().is_none());
+        let data = load(&cache, &location, 0, &miss).await;
+        assert_eq!(data, vec![0; PAGE_SIZE]);
+        assert_eq!(miss.load(Ordering::SeqCst), 1);
+        assert!(l1.get(&location, 0).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_head_reloads_stale_l2_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let l1 = Arc::new(InMemoryCache::new(4 * PAGE_SIZE, PAGE_SIZE));
+        let l2 = DiskCache::builder(tmp_dir.path(), 16 * PAGE_SIZE)
+            .page_size(PAGE_SIZE)
+            .build()
+            .await
+            .unwrap();
+        let cache = TieredCache::new(l1.clone(), Arc::new(l2));
+        let location = Path::from("some/file.lance");
+        let meta = |e_tag: &str| ObjectMeta {
+            location: location.clone(),
+            last_modified: Default::default(),
+            size: 2 * PAGE_SIZE,
+            e_tag: Some(e_tag.to_string()),
+            version: None,
+        };
+        let miss = AtomicUsize::new(0);
+
+        cache
+            .head(&location, async { Ok(meta("v1")) })
+            .await
+            .unwrap();
+        load(&cache, &location, 0, &miss).await;
+
+        // L1 drops its metadata, the object changed since.
+        l1.invalidate(&location).await.unwrap();
+        let m = cache
+            .head(&location, async { Ok(meta("v2")) })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v2"));
+        let m = cache
+            .l2()
+            .head(&location, async { panic!("metadata should be cached") })
+            .await
+            .unwrap();
+        assert_eq!(m.e_tag.as_deref(), Some("v2"));
+        assert!(cache.l2().get(&location, 0).await.unwrap().is_none());
+        load(&cache, &location, 0, &miss).await;
+        assert_eq!(miss.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_demotions() {
+        let demotions = Demotions::new(2);
+        let location = Path::from("some/file.lance");
+        let other = Path::from("some/other.lance");
+
+        let epoch = demotions.start(&location).unwrap();
+        let other_epoch = demotions.start(&other).unwrap();
+        // Too many demotions in flight.
+        assert!(demotions.start(&location).is_none());
+
+        demotions.cancel(&location);
+        assert!(!demotions.is_current(&location, epoch));
+        assert!(demotions.is_current(&other, other_epoch));
+        assert!(!demotions.finish(&location, epoch));
+        assert!(demotions.finish(&other, other_epoch));
+
+        let epoch = demotions.start(&location).unwrap();
+        assert!(demotions.is_current(&location, epoch));
+        assert!(demotions.finish(&location, epoch));
+        assert!(demotions.state.lock().unwrap().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_demotion() {
+        let tmp_dir = tempdir().unwrap();
+        let l2 = DiskCache::builder(tmp_dir.path(), 16 * PAGE_SIZE)
+            .page_size(PAGE_SIZE)
+            .build()
+            .await
+            .unwrap();
+        let cache = TieredCache::with_demotion(
+            InMemoryCache::builder(2 * PAGE_SIZE).page_size(PAGE_SIZE),
+            Arc::new(l2),
+        );
+        let location = Path::from("some/file.lance");
+        let miss = AtomicUsize::new(0);
+
+        for page_id in 0..8 {
+            load(&cache, &location, page_id, &miss).await;
+        }
+        assert_eq!(miss.load(Ordering::SeqCst), 8);
+        // Evictions are processed in the background, and then demoted.
+        let mut demoted = 0;
+        for _ in 0..100 {
+            cache.l1().get(&location, 0).await.unwrap();
+            demoted = 0;
+            for page_id in 0..8 {
+                if cache.l2().get(&location, page_id).await.unwrap().is_some() {
+                    demoted += 1;
+                }
+            }
+            if demoted >= 6 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(demoted >= 6, "demoted {demoted} pages");
+
+        for page_id in 0..8 {
+            let data = load(&cache, &location, page_id, &miss).await;
+            assert_eq!(data, vec![page_id as u8; PAGE_SIZE]);
+        }
+        assert!(miss.load(Ordering::SeqCst) <= 10);
+    }
+}